@@ -0,0 +1,17 @@
+//! Picks the linker script for the boot mode we're building for.
+
+fn main() {
+    let dir = "src/arch/riscv64/lds";
+    let script = if std::env::var_os("CARGO_FEATURE_HIGHER_HALF").is_some() {
+        "virt-sbi-high.lds"
+    } else if std::env::var_os("CARGO_FEATURE_RISCV_SBI").is_some() {
+        "virt-sbi.lds"
+    } else {
+        "virt.lds"
+    };
+
+    // `-L` so the scripts can INCLUDE each other
+    println!("cargo:rustc-link-arg=-L{}", dir);
+    println!("cargo:rustc-link-arg=-T{}/{}", dir, script);
+    println!("cargo:rerun-if-changed={}", dir);
+}
@@ -5,5 +5,5 @@ pub mod riscv64;
 pub use riscv64::*;
 
 pub trait Arch {
-    extern "C" fn kinit();
+    extern "C" fn kinit(hartid: usize, dtb: usize);
 }
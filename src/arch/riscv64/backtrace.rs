@@ -0,0 +1,98 @@
+//! Stack unwinding by following the frame pointer chain
+//!
+//! We build with `-Cforce-frame-pointers=yes`, so every function keeps
+//! `s0`/`fp` pointing just past its frame, with the return address at
+//! `fp - 8` and the caller's `fp` at `fp - 16`. Walking that chain gives a
+//! backtrace without any unwind tables.
+
+use core::mem::size_of;
+
+use super::mm::layout::KernelLayout;
+use crate::{println, util::symbols::Symbolized};
+
+/// Give up after this many frames, in case the chain loops
+pub const MAX_DEPTH: usize = 32;
+
+/// One step up the chain: the return address and the caller's frame pointer
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub ra: usize,
+    pub fp: usize,
+}
+
+/// Iterates over the frames above `fp`, stopping as soon as the chain
+/// leaves the kernel stack.
+pub struct Backtrace {
+    fp: usize,
+    depth: usize,
+}
+
+impl Backtrace {
+    /// Start unwinding from frame pointer `fp`.
+    pub fn from_fp(fp: usize) -> Self {
+        Self { fp, depth: 0 }
+    }
+
+    /// Start unwinding from the caller of this function.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let fp: usize;
+        unsafe { core::arch::asm!("mv {}, s0", out(reg) fp) };
+        Self::from_fp(fp)
+    }
+}
+
+/// Does a frame record at `fp` lie entirely within the kernel stack?
+fn on_kernel_stack(fp: usize) -> bool {
+    let stack = KernelLayout::get().stack;
+    let (start, end) = (stack.start.as_usize(), stack.end.as_usize());
+    fp % size_of::<usize>() == 0 && fp >= start + 2 * size_of::<usize>() && fp <= end
+}
+
+impl Iterator for Backtrace {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.depth >= MAX_DEPTH || !on_kernel_stack(self.fp) {
+            return None;
+        }
+        let record = self.fp as *const usize;
+        let (ra, prev_fp) = unsafe { (*record.sub(1), *record.sub(2)) };
+        if ra == 0 {
+            return None;
+        }
+        // Callers' frames are always further up the stack. Anything else
+        // means we've walked into garbage.
+        self.fp = if prev_fp > self.fp { prev_fp } else { 0 };
+        self.depth += 1;
+        Some(Frame { ra, fp: prev_fp })
+    }
+}
+
+/// Print a backtrace starting with the caller of this function.
+#[inline(always)]
+pub fn print_current() {
+    print(None, Backtrace::current());
+}
+
+/// Print a backtrace for a context that was interrupted at `pc` with
+/// frame pointer `fp`, e.g. from a trap frame.
+pub fn print_from(pc: usize, fp: usize) {
+    print(Some(pc), Backtrace::from_fp(fp));
+}
+
+fn print(pc: Option<usize>, frames: Backtrace) {
+    println!("backtrace:");
+    let mut n = 0;
+    if let Some(pc) = pc {
+        println!("  #{:<2} {}", n, Symbolized(pc));
+        n += 1;
+    }
+    for frame in frames {
+        println!("  #{:<2} {}", n, Symbolized(frame.ra));
+        n += 1;
+    }
+    if n == 0 {
+        println!("  <no frames on the kernel stack>");
+    }
+}
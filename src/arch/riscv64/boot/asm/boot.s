@@ -28,10 +28,16 @@
 ##! 
 ##!
 .option norvc
-.section .data
-
 .section .text.init
 
+.set MAX_HARTS, 8
+.set M_TRAP_STACK_SIZE, 0x4000
+
+# Hart states, see `trap/firmware.rs`
+.set HSM_STARTED, 0
+.set HSM_STOPPED, 1
+.set HSM_START_PENDING, 2
+
 .global _start
 
 _start:
@@ -53,141 +59,188 @@ _start_m_validate_bss:
 		#
 		# This is effectively an assert that the 
 		# start address is before the end address
-		la      a0,     _bss_start     
-		la      a1,     _bss_end
+		#
+		# a0 (hart id) and a1 (device tree) are for supervisor mode,
+		# so stay out of them.
+		la      t0,     _bss_start     
+		la      t1,     _bss_end
 		# skip zeroing if not needed
-		bgeu    a0,     a1, _start_m_delegate_interrupts            
+		bgeu    t0,     t1, _start_m_init_hart            
 
 		# Loop through entire bss section, and zero it all
 _start_m_bss_zero_loop:
-				sd      zero,   (a0)
-				addi    a0,     a0,         8
-				bltu    a0,     a1,         _start_m_bss_zero_loop
-				j _start_m_delegate_interrupts
-		
-_start_m_delegate_interrupts:
-		# TODO delegate interrupts to be handled in s-mode
-
-_start_m_init_stack:
-		# load the stack pointer from
-		# the link script. 	
-		# It is calculated as _bss_end + 0x80000 (524 KiB Total)
-		la sp, _stack_end
+				sd      zero,   (t0)
+				addi    t0,     t0,         8
+				bltu    t0,     t1,         _start_m_bss_zero_loop
+				j _start_m_init_hart
+
+_start_m_init_hart:
+		call _init_machine
+
+# We're the boot hart, so as far as the SBI is concerned we're running
+_start_m_mark_started:
+		la		t0, HSM_STATE
+		li		t1, HSM_STARTED
+		sd		t1, 0(t0)
 
+# `mret` below drops us into supervisor mode
 _start_m_kinit_init_mstatus:
-		.set M_ENABLE_MACHINE_MODE, (0b11 << 11)
-		li		t0, M_ENABLE_MACHINE_MODE
+		.set M_SET_PREV_SUPERVISOR_MODE, (0b01 << 11)
+		li		t0, M_SET_PREV_SUPERVISOR_MODE
 		csrw	mstatus, t0
 
-# Load the `machine trap vector` *rust* function
-# into `mtvec`. This function will now be called
-# every time there is a trap. (syscall, illegal instruction, timer interupt, etc.)
-_start_m_load_trap_vector:
+# Load the supervisor entry point (see `supervisor.s`) into the
+# `Machine Exception Program Counter` CSR and drop down into supervisor
+# mode. QEMU started us with our hart id in a0 and the device tree in a1,
+# which is just what supervisor mode wants.
+_start_m_enter_supervisor_mode:
+		la t1, _start_supervisor_mode_entry
+		csrw mepc, t1
+		mret
+
+# Machine mode setup every hart needs before it can run supervisor mode.
+# Doesn't touch the stack, so it can be called before there is one.
+# Clobbers t0-t2.
+_init_machine:
+
+_init_machine_m_delegate_interrupts:
+		# Hand everything supervisor mode can deal with to supervisor mode.
+		# Misaligned accesses and ecalls from supervisor mode stay here,
+		# see `trap/machine.rs`.
+		.set M_DELEG_INSTRUCTION_ACCESS_FAULT, (1 << 1)
+		.set M_DELEG_ILLEGAL_INSTRUCTION, (1 << 2)
+		.set M_DELEG_BREAKPOINT, (1 << 3)
+		.set M_DELEG_LOAD_ACCESS_FAULT, (1 << 5)
+		.set M_DELEG_STORE_ACCESS_FAULT, (1 << 7)
+		.set M_DELEG_USER_ECALL, (1 << 8)
+		.set M_DELEG_INSTRUCTION_PAGE_FAULT, (1 << 12)
+		.set M_DELEG_LOAD_PAGE_FAULT, (1 << 13)
+		.set M_DELEG_STORE_PAGE_FAULT, (1 << 15)
+
+		li		t0, M_DELEG_INSTRUCTION_ACCESS_FAULT | M_DELEG_ILLEGAL_INSTRUCTION | M_DELEG_BREAKPOINT | M_DELEG_LOAD_ACCESS_FAULT | M_DELEG_STORE_ACCESS_FAULT | M_DELEG_USER_ECALL | M_DELEG_INSTRUCTION_PAGE_FAULT | M_DELEG_LOAD_PAGE_FAULT | M_DELEG_STORE_PAGE_FAULT
+		csrw	medeleg, t0
+
+		.set M_DELEG_SUPERVISOR_SOFTWARE, (1 << 1)
+		.set M_DELEG_SUPERVISOR_TIMER, (1 << 5)
+		.set M_DELEG_SUPERVISOR_EXTERNAL, (1 << 9)
+
+		li		t0, M_DELEG_SUPERVISOR_SOFTWARE | M_DELEG_SUPERVISOR_TIMER | M_DELEG_SUPERVISOR_EXTERNAL
+		csrw	mideleg, t0
+
+# Without any PMP entries, supervisor mode can't touch memory at all.
+# Give it everything with a single top-of-range entry.
+_init_machine_m_init_pmp:
+		.set M_PMP_TOR_RWX, (0b01 << 3) | 0b111
+		li		t0, 0x3fffffffffffff
+		csrw	pmpaddr0, t0
+		li		t0, M_PMP_TOR_RWX
+		csrw	pmpcfg0, t0
+
+# Let supervisor mode read `time` (and `cycle`/`instret`) directly
+_init_machine_m_init_counters:
+		.set M_COUNTEREN_CY_TM_IR, 0b111
+		li		t0, M_COUNTEREN_CY_TM_IR
+		csrw	mcounteren, t0
+
+# Load the `machine trap vector` into `mtvec`.
+# This is now only called for the traps we haven't delegated above.
+_init_machine_m_load_trap_vector:
 		la t2, m_trap_vector
 		csrw mtvec, t2
 
-
-# Load the kinit function address 
-# into the `Machine Exception Program Counter` CSR
-# 
-# Set return address to go into supervisor mode
-#
-# And jump to `kinit`
-_start_m_m_kinit:
-		la t1, kinit
-		csrw mepc, t1
-
-		la ra, _start_supervisor_mode_entry
-		mret
+# Software interrupts are how other harts get our attention. Machine mode
+# passes them on to supervisor mode, see `trap/machine.rs`. The timer is
+# enabled on demand by the SBI `set_timer` call.
+_init_machine_m_enable_msip:
+		.set M_ENABLE_SOFTWARE_INTERRUPTS, (1 << 3)
+		li		t0, M_ENABLE_SOFTWARE_INTERRUPTS
+		csrw	mie, t0
+
+# `m_trap_vector` expects `mscratch` to hold the top of this
+# hart's machine trap stack, which is the end of slot `mhartid`.
+_init_machine_m_init_mscratch:
+		csrr	t0, mhartid
+		addi	t0, t0, 1
+		li		t1, M_TRAP_STACK_SIZE
+		mul		t0, t0, t1
+		la		t1, MACHINE_TRAP_STACKS
+		add		t0, t0, t1
+		csrw	mscratch, t0
+		ret
 
 
 # =========================================================================================
-# ===================================== SUPERVISOR MODE  ==================================
+# ===================================== PARKED HARTS  =====================================
 # =========================================================================================
 
-_start_supervisor_mode_entry:
-
-_start_s_kmain_init_sstatus:
-		.set S_SET_SUPERVISOR_SPP, (1 << 8)
-		.set S_ENABLE_INTERRUPTS, (1 << 1)
-		.set S_SET_PREV_INTERRUPT_ENABLED, (1 << 5)
-
-		li		t0, S_SET_SUPERVISOR_SPP | S_ENABLE_INTERRUPTS | S_SET_PREV_INTERRUPT_ENABLED
-		csrw	sstatus, t0
-
-
-_start_s_kmain_init_sie:
-		.set S_ENABLE_SOFTWARE_INTERRUPTS, (1 << 1)
-		.set S_ENABLE_TIMER_INTERRUPTS, (1 << 5)
-		.set S_ENABLE_EXTERNAL_INTERRUPTS, (1 << 9)
-
-		li		t1, S_ENABLE_TIMER_INTERRUPTS | S_ENABLE_TIMER_INTERRUPTS | S_ENABLE_EXTERNAL_INTERRUPTS
-		csrw	sie, t1
-
-_start_s_init_stvec:
-		la		t3, s_trap_vector
-		csrw stvec, t3
-
-_start_s_set_mpp:
-		.set S_ENABLE_SUPERVISOR_MODE, (0b01 << 11)
-		li		t0, S_ENABLE_SUPERVISOR_MODE
-		csrw	mstatus, t0
-		
-# Load the kmain function address 
-# into the `Supervisor Exception Program Counter` CSR
-# This is technically needed only when executing 
-# a S-mode to U-mode change, which we are NOT 
-# performing here (note the lack of `sret` below)
-_start_s_load_kmain:
-		la t4, kmain
-		csrw sepc, t4
-
-_start_s_return:
-		jal kmain
-
-# Note: i stole this code, i dont actually really know what or why it does. will revisit post-paging impl
+# Every hart but #0 ends up here, and so does any hart supervisor mode
+# stops through the SBI. It sets up machine mode, marks itself stopped in
+# `HSM_STATE` and then sleeps until `hart_start` (see `trap/firmware.rs`)
+# moves it to `HSM_START_PENDING` and raises its machine software
+# interrupt (MSIP) in the Core Local Interruptor (CLINT). The MSIP bit for
+# a hart lives at base_address + hart * 4, where base address is
+# 0x0200_0000 (MMIO CLINT base address).
+.global hart_parking_lot
 hart_parking_lot:
-    # Parked harts go here. We need to set these
-	# to only awaken if it receives a software interrupt,
-	# which we're going to call the SIPI (Software Intra-Processor Interrupt).
-	# We call the SIPI by writing the software interrupt into the Core Local Interruptor (CLINT)
-	# Which is calculated by: base_address + hart * 4
-	# where base address is 0x0200_0000 (MMIO CLINT base address)
-	# We only use additional harts to run user-space programs, although this may
-	# change.
-
-	# We divide up the stack so the harts aren't clobbering one another.
-	la		sp, _stack_end
-	li		t0, 0x10000
-	csrr	a0, mhartid
-	mul		t0, t0, a0
-	sub		sp, sp, t0
-
-    # The parked harts will be put into machine mode with interrupts enabled.
-	li		t0, 0b11 << 11 | (1 << 7)
-	csrw	mstatus, t0
-	# Allow for MSIP (Software interrupt). We will write the MSIP from hart #0 to awaken these parked harts.
-	li		t3, (1 << 3) | ~(1 << 5)
-	csrw	mie, t3
-    # Machine's exception program counter (MEPC) is set to the Rust initialization
-	# code and waiting loop.
-	la		t1, kinit_hart
-	csrw	mepc, t1
-
-    # Machine's trap vector base address is set to `m_trap_vector`, for
-	# "machine" trap vector. The Rust initialization routines will give each
-	# hart its own trap frame. We can use the same trap function and distinguish
-	# between each hart by looking at the trap frame.
-	la		t2, m_trap_vector
-	csrw	mtvec, t2
-    # Whenever our hart is done initializing, we want it to return to the waiting
-	# loop, which is just below mret.
-	la		ra, wfi_loop
-	# We use mret here so that the mstatus register is properly updated.
-	mret
-
-
-wfi_loop:
+		csrr	a0, mhartid
+		# We only have trap stacks and frames for MAX_HARTS harts
+		li		t0, MAX_HARTS
+		bgeu	a0, t0, _hart_parking_lot_m_forever
+
+_hart_parking_lot_m_init_hart:
+		call _init_machine
+
+# t3 is our offset into the per hart arrays, t4 our `HSM_STATE` slot
+_hart_parking_lot_m_announce:
+		slli	t3, a0, 3
+		la		t4, HSM_STATE
+		add		t4, t4, t3
+		li		t0, HSM_STOPPED
+		fence	rw, w
+		sd		t0, 0(t4)
+
+# Machine interrupts stay globally off (mstatus.MIE = 0), but with MSIE
+# enabled in `mie` by `_init_machine` a software interrupt still wakes us
+# from `wfi`.
+_hart_parking_lot_m_find_msip:
+		csrw	mstatus, zero
+		.set CLINT_MSIP_BASE, 0x02000000
+		li		t1, CLINT_MSIP_BASE
+		slli	t2, a0, 2
+		add		t1, t1, t2
+
+# `wfi` may return for no reason at all, and MSIP may have been raised
+# for something else, so check we've really been started. MSIP is
+# cleared before looking so a start that comes in after can't be missed.
+_hart_parking_lot_m_wait:
 		wfi
-		j wfi_loop
+		sw		zero, 0(t1)
+		ld		t0, 0(t4)
+		fence	r, rw
+		li		t2, HSM_START_PENDING
+		bne		t0, t2, _hart_parking_lot_m_wait
+
+# Start where `hart_start` asked, with our hart id in a0 and its
+# `opaque` in a1
+_hart_parking_lot_m_load_start:
+		la		t0, HSM_START_ADDR
+		add		t0, t0, t3
+		ld		t0, 0(t0)
+		csrw	mepc, t0
+		la		t0, HSM_START_OPAQUE
+		add		t0, t0, t3
+		ld		a1, 0(t0)
+		li		t0, HSM_STARTED
+		fence	rw, w
+		sd		t0, 0(t4)
+
+_hart_parking_lot_m_enter_supervisor_mode:
+		csrw	satp, zero
+		li		t0, M_SET_PREV_SUPERVISOR_MODE
+		csrw	mstatus, t0
+		mret
 
+# Harts we have no room for sleep here for good
+_hart_parking_lot_m_forever:
+		wfi
+		j _hart_parking_lot_m_forever
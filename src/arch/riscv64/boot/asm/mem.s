@@ -47,3 +47,19 @@ KERNEL_STACK_START:
     .global KERNEL_STACK_END
 KERNEL_STACK_END:
     .dword _stack_end
+
+    .global KSYMTAB_START
+KSYMTAB_START:
+    .dword _ksymtab_start
+
+    .global KSYMTAB_END
+KSYMTAB_END:
+    .dword _ksymtab_end
+
+    .global KPARAMS_START
+KPARAMS_START:
+    .dword _kparams_start
+
+    .global KPARAMS_END
+KPARAMS_END:
+    .dword _kparams_end
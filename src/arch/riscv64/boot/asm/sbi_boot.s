@@ -0,0 +1,107 @@
+##! Boot assembly for running under SBI firmware (the `riscv-sbi` feature)
+##!
+##! The firmware owns machine mode and jumps to `_start` in supervisor
+##! mode with paging off, the hart id in a0 and the device tree in a1.
+##! Usually only the boot hart comes through here and the rest wait to
+##! be started through the HSM extension (see `smp.rs`), but older
+##! firmware lets every hart in, so the first one to get here wins and
+##! the others stop themselves.
+##!
+##! Labels follow the conventions described in `boot.s`.
+.option norvc
+.section .data
+
+.balign 8
+_start_boot_lottery:
+		.dword 0
+
+.section .text.init
+
+.set MAX_HARTS, 8
+.set SBI_HSM_EID, 0x48534d
+.set SBI_HSM_HART_STOP, 1
+
+.global _start
+
+# The RISC-V flavour of the Linux arm64 Image header, so firmware and
+# bootloaders that load Linux can load us. See
+# Documentation/riscv/boot-image-header.rst in the Linux tree.
+_start:
+		# code0/code1: jump over the rest of the header
+		j		_start_s_main
+		.balign 8
+		# text_offset: where in RAM we want to be loaded
+		.dword	0x200000
+		# image_size
+		.dword	_kernel_size
+		# flags: little endian
+		.dword	0
+		# version 0.2
+		.word	2
+		# res1
+		.word	0
+		# res2
+		.dword	0
+		# magic (deprecated)
+		.ascii	"RISCV\0\0\0"
+		# magic2
+		.ascii	"RSC\x05"
+		# res3: offset of the PE/COFF header, we don't have one
+		.word	0
+
+_start_s_main:
+		csrw	satp, zero
+		csrw	sie, zero
+		.option push
+		.option norelax
+		la gp, _global_pointer
+		.option pop
+
+		# We only have stacks and trap frames for MAX_HARTS harts
+		li		t0, MAX_HARTS
+		bgeu	a0, t0, _start_s_stop
+
+_start_s_lottery:
+		la		t0, _start_boot_lottery
+		li		t1, 1
+		amoswap.w	t1, t1, (t0)
+		bnez	t1, _start_s_stop
+
+_start_s_validate_bss:
+		# a0 (hart id) and a1 (device tree) are passed on to `kinit`,
+		# so stay out of them.
+		la      t0,     _bss_start     
+		la      t1,     _bss_end
+		# skip zeroing if not needed
+		bgeu    t0,     t1, _start_s_enter_kernel
+
+		# Loop through entire bss section, and zero it all
+_start_s_bss_zero_loop:
+				sd      zero,   (t0)
+				addi    t0,     t0,         8
+				bltu    t0,     t1,         _start_s_bss_zero_loop
+
+# Join the machine mode boot path, see `supervisor.s`. The higher half
+# kernel has to turn paging on first, see `trampoline.s`.
+_start_s_enter_kernel:
+.ifdef HIGHER_HALF
+		call	_trampoline_build
+		call	_trampoline_enter
+		# gp still holds the physical address
+		.option push
+		.option norelax
+		la gp, _global_pointer
+		.option pop
+.endif
+		j		_start_supervisor_mode_entry
+
+# Lost the lottery (or there's no room for us). Hand the hart back to
+# the firmware, the boot hart starts it again through HSM when it is
+# ready. Without HSM all we can do is sleep.
+_start_s_stop:
+		li		a7, SBI_HSM_EID
+		li		a6, SBI_HSM_HART_STOP
+		ecall
+_start_s_forever:
+		wfi
+		j		_start_s_forever
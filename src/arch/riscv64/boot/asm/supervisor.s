@@ -0,0 +1,83 @@
+##! Supervisor mode entry points for macaque
+##!
+##! Both boot paths end up here: `boot.s` after setting up machine mode
+##! itself, and `sbi_boot.s` when SBI firmware has already done that.
+##! Either way we arrive in supervisor mode with paging off, the hart id
+##! in a0 and, for the boot hart, the address of the device tree in a1.
+##!
+##! Labels follow the conventions described in `boot.s`.
+.option norvc
+.section .text
+
+.set HART_STACK_SIZE, 0x10000
+
+.global _start_supervisor_mode_entry
+_start_supervisor_mode_entry:
+
+# Each hart gets its own 64 KiB slice of the boot stack, indexed by hart id
+_start_s_init_stack:
+		la		sp, _stack_end
+		li		t0, HART_STACK_SIZE
+		mul		t0, t0, a0
+		sub		sp, sp, t0
+
+_start_s_init_stvec:
+		la		t3, s_trap_vector
+		csrw stvec, t3
+
+# Interrupts are still off, bring up the rest of the kernel.
+# `kinit(hartid, dtb)` also points `sscratch` at our trap frame.
+_start_s_kinit:
+		call kinit
+
+_start_s_kmain_init_sie:
+		.set S_ENABLE_SOFTWARE_INTERRUPTS, (1 << 1)
+		.set S_ENABLE_TIMER_INTERRUPTS, (1 << 5)
+		.set S_ENABLE_EXTERNAL_INTERRUPTS, (1 << 9)
+
+		li		t1, S_ENABLE_SOFTWARE_INTERRUPTS | S_ENABLE_TIMER_INTERRUPTS | S_ENABLE_EXTERNAL_INTERRUPTS
+		csrw	sie, t1
+
+_start_s_kmain_init_sstatus:
+		.set S_SET_SUPERVISOR_SPP, (1 << 8)
+		.set S_ENABLE_INTERRUPTS, (1 << 1)
+		.set S_SET_PREV_INTERRUPT_ENABLED, (1 << 5)
+
+		li		t0, S_SET_SUPERVISOR_SPP | S_ENABLE_INTERRUPTS | S_SET_PREV_INTERRUPT_ENABLED
+		csrw	sstatus, t0
+
+_start_s_kmain:
+		call kmain
+
+# kmain returned, there is nothing left to do but wait for interrupts
+_start_s_idle:
+		wfi
+		j _start_s_idle
+
+# Where secondary harts enter supervisor mode once the boot hart has
+# woken them up, with their hart id in a0.
+.global _secondary_supervisor_mode_entry
+_secondary_supervisor_mode_entry:
+
+_secondary_s_init_stack:
+		la		sp, _stack_end
+		li		t0, HART_STACK_SIZE
+		mul		t0, t0, a0
+		sub		sp, sp, t0
+
+_secondary_s_init_stvec:
+		la		t3, s_trap_vector
+		csrw	stvec, t3
+
+# `kinit_hart` points `sscratch` at this hart's trap frame, sets up
+# paging and interrupts and reports back to the boot hart.
+_secondary_s_kinit_hart:
+		.option push
+		.option norelax
+		la gp, _global_pointer
+		.option pop
+		call kinit_hart
+
+_secondary_s_idle:
+		wfi
+		j _secondary_s_idle
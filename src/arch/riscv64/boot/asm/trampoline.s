@@ -0,0 +1,135 @@
+##! Getting into the higher half (the `higher-half` feature)
+##!
+##! The kernel is linked at KERNEL_BASE but runs wherever the firmware
+##! loaded it, with paging off, until it gets here. `_trampoline_enter`
+##! turns paging on with an Sv39 boot table (whatever mode `mm::mode`
+##! picks later) that maps
+##!
+##!   - the gigabyte we're running in 1:1, so the next fetch still works,
+##!   - KERNEL_BASE onwards to where we were loaded, in 2 MiB pages,
+##!   - the first DIRECT_MAP_GIGS GiB of physical memory at DIRECT_MAP_BASE,
+##!
+##! and returns to the link address of its caller. Once `mm::init` has
+##! built the kernel's own table (which has no identity map), the boot
+##! table is only used by secondary harts on their way in.
+##!
+##! The constants have to agree with `mm/addr.rs`.
+##!
+##! Labels follow the conventions described in `boot.s`.
+.option norvc
+
+.set PTE_V, 1 << 0
+# V, R, W, X, G, A and D
+.set PTE_LEAF, 0xef
+.set SATP_SV39, 8 << 60
+# Index into the root table of KERNEL_BASE (0xffffffc000000000)
+.set KERNEL_VPN2, 0x100
+# Index into the root table of DIRECT_MAP_BASE (0xffffffd000000000)
+.set DIRECT_MAP_VPN2, 0x140
+.set DIRECT_MAP_GIGS, 64
+# How far apart the PTEs of consecutive 2 MiB and 1 GiB pages are
+.set MEGAPAGE_PTE_STEP, (1 << 21) >> 2
+.set GIGAPAGE_PTE_STEP, (1 << 30) >> 2
+
+.section .data
+
+.balign 8
+# Physical address of `_start`, see `mm::addr`
+.global KERNEL_LOAD_ADDR
+KERNEL_LOAD_ADDR:
+		.dword 0
+
+# Link address of `_start`
+_trampoline_link_start:
+		.dword _start
+
+.section .bss
+
+.balign 4096
+_trampoline_root:
+		.skip 4096
+_trampoline_l1:
+		.skip 4096
+
+.section .text.init
+
+# Fill in the boot table. Boot hart only, with paging off and bss
+# cleared. Clobbers t0-t5.
+.global _trampoline_build
+_trampoline_build:
+		lla		t0, _start
+		lla		t1, KERNEL_LOAD_ADDR
+		sd		t0, (t1)
+		lla		t1, _trampoline_root
+
+_trampoline_build_s_identity:
+		srli	t2, t0, 30
+		slli	t3, t2, 3
+		add		t3, t3, t1
+		slli	t2, t2, 28
+		ori		t2, t2, PTE_LEAF
+		sd		t2, (t3)
+
+# The root entry for KERNEL_BASE points at the level 1 table, whose
+# entries map 1 GiB starting where we were loaded
+_trampoline_build_s_kernel:
+		lla		t2, _trampoline_l1
+		srli	t3, t2, 12
+		slli	t3, t3, 10
+		ori		t3, t3, PTE_V
+		li		t4, KERNEL_VPN2 * 8
+		add		t4, t4, t1
+		sd		t3, (t4)
+
+		srli	t3, t0, 12
+		slli	t3, t3, 10
+		ori		t3, t3, PTE_LEAF
+		li		t4, 512
+		li		t5, MEGAPAGE_PTE_STEP
+_trampoline_build_s_kernel_loop:
+		sd		t3, (t2)
+		add		t3, t3, t5
+		addi	t2, t2, 8
+		addi	t4, t4, -1
+		bnez	t4, _trampoline_build_s_kernel_loop
+
+_trampoline_build_s_direct_map:
+		li		t2, DIRECT_MAP_VPN2 * 8
+		add		t2, t2, t1
+		li		t3, PTE_LEAF
+		li		t4, DIRECT_MAP_GIGS
+		li		t5, GIGAPAGE_PTE_STEP
+_trampoline_build_s_direct_map_loop:
+		sd		t3, (t2)
+		add		t3, t3, t5
+		addi	t2, t2, 8
+		addi	t4, t4, -1
+		bnez	t4, _trampoline_build_s_direct_map_loop
+		ret
+
+# Switch to the boot table and return to the link address of our
+# caller. Any hart, with paging off. Clobbers t0 and t1.
+.global _trampoline_enter
+_trampoline_enter:
+		lla		t0, _trampoline_root
+		srli	t0, t0, 12
+		li		t1, SATP_SV39
+		or		t0, t0, t1
+		sfence.vma
+		csrw	satp, t0
+		sfence.vma
+
+_trampoline_enter_s_relocate:
+		lla		t0, _start
+		lla		t1, _trampoline_link_start
+		ld		t1, (t1)
+		sub		t1, t1, t0
+		add		ra, ra, t1
+		ret
+
+# Where secondary harts start when the kernel lives in the higher half,
+# see `smp.rs`. Same as `_secondary_supervisor_mode_entry` otherwise.
+.global _secondary_trampoline_entry
+_secondary_trampoline_entry:
+		call	_trampoline_enter
+		j		_secondary_supervisor_mode_entry
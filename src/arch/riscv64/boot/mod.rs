@@ -1,4 +1,39 @@
-use core::arch::global_asm;
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use super::mm::addr::phys_to_virt;
+
+#[cfg(not(feature = "riscv-sbi"))]
 global_asm!(include_str!("asm/boot.s"));
+#[cfg(all(feature = "riscv-sbi", not(feature = "higher-half")))]
+global_asm!(include_str!("asm/sbi_boot.s"));
+#[cfg(feature = "higher-half")]
+global_asm!(
+    ".set HIGHER_HALF, 1",
+    include_str!("asm/sbi_boot.s"),
+    include_str!("asm/trampoline.s")
+);
+global_asm!(include_str!("asm/supervisor.s"));
 global_asm!(include_str!("asm/mem.s"));
+
+/// Physical address of the device tree we were booted with, 0 if none
+static DTB: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) fn set_dtb(addr: usize) {
+    DTB.store(addr, Ordering::Relaxed);
+}
+
+/// Physical address of the device tree the firmware (or QEMU) handed us
+pub fn dtb() -> Option<usize> {
+    match DTB.load(Ordering::Relaxed) {
+        0 => None,
+        addr => Some(addr),
+    }
+}
+
+/// Where we can read the device tree, in the direct map
+pub fn dtb_ptr() -> Option<*const u8> {
+    dtb().map(|addr| phys_to_virt(addr) as *const u8)
+}
@@ -0,0 +1,50 @@
+//! Accessors for the control and status registers we use from Rust.
+//!
+//! Every CSR gets its own module with `read`, `write`, `set` and `clear`.
+//! `set` and `clear` take a bit mask and use `csrs`/`csrc`, so they are
+//! atomic with respect to traps on the current hart.
+
+macro_rules! csr {
+    ($($name:ident),+ $(,)?) => {
+        $(
+            pub mod $name {
+                #[inline(always)]
+                pub fn read() -> usize {
+                    let val: usize;
+                    unsafe {
+                        core::arch::asm!(concat!("csrr {}, ", stringify!($name)), out(reg) val);
+                    }
+                    val
+                }
+
+                /// # Safety
+                ///
+                /// Writing a CSR changes the state of the hart in ways the
+                /// compiler can't see.
+                #[inline(always)]
+                pub unsafe fn write(val: usize) {
+                    core::arch::asm!(concat!("csrw ", stringify!($name), ", {}"), in(reg) val);
+                }
+
+                /// # Safety
+                ///
+                /// See [`write`].
+                #[inline(always)]
+                pub unsafe fn set(mask: usize) {
+                    core::arch::asm!(concat!("csrs ", stringify!($name), ", {}"), in(reg) mask);
+                }
+
+                /// # Safety
+                ///
+                /// See [`write`].
+                #[inline(always)]
+                pub unsafe fn clear(mask: usize) {
+                    core::arch::asm!(concat!("csrc ", stringify!($name), ", {}"), in(reg) mask);
+                }
+            }
+        )+
+    };
+}
+
+csr!(sstatus, sie, sip, stvec, sscratch, sepc, scause, stval, satp);
+csr!(mstatus, mie, mip, mhartid);
@@ -0,0 +1,139 @@
+//! Inter-processor interrupts
+//!
+//! [`send_ipi`] records what the target harts should do in their
+//! `PerCpu::ipi_pending` bits and raises a software interrupt on them
+//! through the SBI. The targets pick the requests up in [`handle_interrupt`].
+//!
+//! [`call_on_harts`] builds on that to run a function on other harts and
+//! wait until they have all finished.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{irq, percpu, sbi, smp};
+use crate::{println, sync::spinlock::SpinLock};
+
+/// What a hart is being interrupted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum IpiKind {
+    /// Something changed that the scheduler on the target should look at
+    Reschedule = 1 << 0,
+    /// Page tables changed, drop every cached translation
+    TlbShootdown = 1 << 1,
+    /// Stop doing anything, e.g. because another hart panicked
+    Stop = 1 << 2,
+    /// Run the function queued by `call_on_harts`
+    Call = 1 << 3,
+}
+
+/// Serializes `call_on_harts`, there is a single call slot
+static CALL_LOCK: SpinLock<()> = SpinLock::new(());
+/// `fn(hartid)` to run for the current call
+static CALL_FN: AtomicUsize = AtomicUsize::new(0);
+/// Targets of the current call that haven't finished it yet
+static CALL_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Interrupt every online hart in `hart_mask` with `kind`. Bits for
+/// offline harts are ignored. Returns the mask of harts signalled.
+pub fn send_ipi(hart_mask: usize, kind: IpiKind) -> usize {
+    let mask = hart_mask & smp::online_harts();
+    if mask == 0 {
+        return 0;
+    }
+    for hart in (0..usize::BITS as usize).filter(|h| mask & (1 << h) != 0) {
+        percpu::of(hart)
+            .ipi_pending
+            .fetch_or(kind as usize, Ordering::Release);
+    }
+    // The pending bits have to be visible before the interrupt is
+    // raised. The Release on `ipi_pending` and the SBI ecall order them.
+    let ret = sbi::send_ipi(mask, 0);
+    if ret.error != sbi::SBI_SUCCESS {
+        println!("send_ipi({:#b}, {:?}) failed: {}", mask, kind, ret.error);
+    }
+    mask
+}
+
+/// Run `f(hartid)` on every online hart in `hart_mask` and wait for all
+/// of them to return. If the calling hart is in the mask, it runs `f`
+/// too, with interrupts masked like everyone else.
+///
+/// The other harts have to take interrupts for this to finish, so it must
+/// not be called with interrupts disabled.
+pub fn call_on_harts(hart_mask: usize, f: fn(usize)) {
+    let me = percpu::hartid();
+    let others = hart_mask & smp::online_harts() & !(1 << me);
+
+    if others != 0 {
+        debug_assert!(
+            irq::irqs_enabled(),
+            "call_on_harts would deadlock with interrupts disabled"
+        );
+        // Deliberately not `lock_irqsave`: we have to keep answering
+        // other harts' calls while we wait for our turn.
+        let _slot = CALL_LOCK.lock();
+        CALL_FN.store(f as usize, Ordering::Relaxed);
+        CALL_PENDING.store(others, Ordering::Release);
+        send_ipi(others, IpiKind::Call);
+
+        if hart_mask & (1 << me) != 0 {
+            irq::without_irqs(|| f(me));
+        }
+        while CALL_PENDING.load(Ordering::Acquire) != 0 {
+            core::hint::spin_loop();
+        }
+    } else if hart_mask & (1 << me) != 0 {
+        irq::without_irqs(|| f(me));
+    }
+}
+
+/// Flush the TLBs of the harts in `hart_mask`, waiting until they're done.
+/// The SBI does this for us and doesn't need the targets to take
+/// interrupts.
+pub fn tlb_shootdown(hart_mask: usize) {
+    let mask = hart_mask & smp::online_harts();
+    if mask != 0 {
+        sbi::remote_sfence_vma(mask, 0, 0, usize::MAX);
+    }
+}
+
+/// Stop every other online hart. Doesn't wait, this is for panics.
+pub fn stop_others() {
+    let online = smp::online_harts();
+    // Before the secondaries are up `tp` may not even be set yet
+    if online.count_ones() <= 1 {
+        return;
+    }
+    send_ipi(online & !(1 << percpu::hartid()), IpiKind::Stop);
+}
+
+/// Called from the trap handler on a supervisor software interrupt.
+pub fn handle_interrupt(hartid: usize) {
+    irq::debug_assert_disabled();
+    // Clear the interrupt before looking at the requests, so one sent
+    // after we've looked raises it again instead of being lost.
+    unsafe { super::csr::sip::clear(super::trap::Interrupt::SupervisorSoftware.mask()) };
+
+    let cpu = percpu::current();
+    cpu.counters.ipis.fetch_add(1, Ordering::Relaxed);
+    let pending = cpu.ipi_pending.swap(0, Ordering::Acquire);
+
+    if pending & IpiKind::TlbShootdown as usize != 0 {
+        unsafe { core::arch::asm!("sfence.vma") };
+    }
+    if pending & IpiKind::Call as usize != 0 {
+        let f = CALL_FN.load(Ordering::Relaxed);
+        let f: fn(usize) = unsafe { core::mem::transmute(f) };
+        f(hartid);
+        CALL_PENDING.fetch_and(!(1 << hartid), Ordering::Release);
+    }
+    if pending & IpiKind::Stop as usize != 0 {
+        smp::mark_offline(hartid);
+        sbi::hart_stop();
+        loop {
+            unsafe { core::arch::asm!("wfi") };
+        }
+    }
+    // Nothing to do for `Reschedule` until there is a scheduler, taking
+    // the interrupt is all that's needed to get out of `wfi`.
+}
@@ -0,0 +1,68 @@
+//! Masking interrupts on the current hart
+//!
+//! Anything a trap handler shares with regular kernel code has to be
+//! touched with interrupts off, or the handler can interrupt the hart
+//! halfway through and deadlock on (or tear) the same state. [`disable`]
+//! clears `sstatus.SIE` and hands back a guard that puts it back the way
+//! it was, so guards nest: only the outermost one turns interrupts back on.
+
+const SSTATUS_SIE: usize = 1 << 1;
+
+/// Are supervisor interrupts enabled on this hart?
+#[inline]
+pub fn irqs_enabled() -> bool {
+    super::csr::sstatus::read() & SSTATUS_SIE != 0
+}
+
+/// Restores `sstatus.SIE` to what it was before [`disable`] when dropped
+#[must_use = "interrupts are restored as soon as the guard is dropped"]
+pub struct IrqGuard {
+    was_enabled: bool,
+}
+
+impl Drop for IrqGuard {
+    #[inline]
+    fn drop(&mut self) {
+        if self.was_enabled {
+            unsafe { super::csr::sstatus::set(SSTATUS_SIE) };
+        }
+    }
+}
+
+/// Mask interrupts on this hart until the returned guard is dropped.
+#[inline]
+pub fn disable() -> IrqGuard {
+    let prev: usize;
+    // Read and clear SIE (bit 1) in one go, so an interrupt can't sneak in
+    // between the two
+    unsafe { core::arch::asm!("csrrci {}, sstatus, 0b10", out(reg) prev) };
+    IrqGuard {
+        was_enabled: prev & SSTATUS_SIE != 0,
+    }
+}
+
+/// Unmask interrupts on this hart.
+///
+/// # Safety
+///
+/// Anything a trap handler shares with the caller must be in a state the
+/// handler can cope with.
+#[inline]
+pub unsafe fn enable() {
+    super::csr::sstatus::set(SSTATUS_SIE);
+}
+
+/// Run `f` with interrupts masked on this hart.
+#[inline]
+pub fn without_irqs<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = disable();
+    f()
+}
+
+/// Panic (in debug builds) if interrupts are enabled. For functions that
+/// must only run from a trap handler or under an [`IrqGuard`].
+#[inline]
+#[track_caller]
+pub fn debug_assert_disabled() {
+    debug_assert!(!irqs_enabled(), "called with interrupts enabled");
+}
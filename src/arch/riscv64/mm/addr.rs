@@ -0,0 +1,168 @@
+//! Physical and kernel virtual addresses
+//!
+//! The kernel reaches memory two ways: its own image, at its link
+//! address, and everything else through the direct map, which puts all
+//! of physical memory at [`DIRECT_MAP_BASE`] onwards. [`PhysAddr`] and
+//! [`VirtAddr`] keep the two kinds of address apart, the functions here
+//! convert between them.
+
+use core::fmt;
+
+use super::page::PAGE_SIZE;
+use crate::util::Address;
+
+/// Where the kernel image is linked with `higher-half`, see
+/// `lds/virt-sbi-high.lds`
+pub const KERNEL_BASE: usize = 0xffff_ffc0_0000_0000;
+
+/// Where physical address 0 shows up in the kernel's address space. All
+/// RAM is mapped from here on, at its physical address plus this. Without
+/// `higher-half`, the direct map is the identity map.
+#[cfg(feature = "higher-half")]
+pub const DIRECT_MAP_BASE: usize = 0xffff_ffd0_0000_0000;
+#[cfg(not(feature = "higher-half"))]
+pub const DIRECT_MAP_BASE: usize = 0;
+
+/// The virtual range [`heap`](super::heap) maps frames into as it grows,
+/// clear of the image, the direct map and, without `higher-half`, of RAM
+#[cfg(feature = "higher-half")]
+pub const HEAP_BASE: usize = 0xffff_ffe0_0000_0000;
+#[cfg(not(feature = "higher-half"))]
+pub const HEAP_BASE: usize = 0x20_0000_0000;
+pub const HEAP_MAX: usize = 16 << 30;
+
+#[cfg(feature = "higher-half")]
+extern "C" {
+    /// Set by the trampoline, see `boot/asm/trampoline.s`
+    static KERNEL_LOAD_ADDR: usize;
+}
+
+/// How far the kernel image is linked above where it was loaded
+pub fn kernel_offset() -> usize {
+    #[cfg(feature = "higher-half")]
+    return KERNEL_BASE - unsafe { KERNEL_LOAD_ADDR };
+    #[cfg(not(feature = "higher-half"))]
+    return 0;
+}
+
+/// Is `vaddr` inside the kernel image (as opposed to the direct map)?
+#[cfg(feature = "higher-half")]
+pub fn is_kernel_image(vaddr: usize) -> bool {
+    (KERNEL_BASE..DIRECT_MAP_BASE).contains(&vaddr)
+}
+
+/// Without `higher-half` the image is in the direct map like everything else
+#[cfg(not(feature = "higher-half"))]
+pub fn is_kernel_image(_vaddr: usize) -> bool {
+    false
+}
+
+/// Where the direct map puts `paddr`
+pub const fn phys_to_virt(paddr: usize) -> usize {
+    paddr.wrapping_add(DIRECT_MAP_BASE)
+}
+
+/// The physical address behind a kernel virtual address, i.e. one in the
+/// kernel image or the direct map
+pub fn virt_to_phys(vaddr: usize) -> usize {
+    if is_kernel_image(vaddr) {
+        vaddr - kernel_offset()
+    } else {
+        vaddr.wrapping_sub(DIRECT_MAP_BASE)
+    }
+}
+
+/// A physical address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct PhysAddr(usize);
+
+/// A virtual address in the kernel's address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct VirtAddr(usize);
+
+impl PhysAddr {
+    pub const fn new(addr: usize) -> Self {
+        Self(addr)
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    /// Where the direct map puts us, see [`phys_to_virt`]
+    pub const fn to_virt(self) -> VirtAddr {
+        VirtAddr(phys_to_virt(self.0))
+    }
+
+    pub const fn add(self, offset: usize) -> Self {
+        Self(self.0 + offset)
+    }
+
+    pub const fn page_offset(self) -> usize {
+        self.0 & (PAGE_SIZE - 1)
+    }
+
+    pub const fn page_align_down(self) -> Self {
+        Self(self.0 & !(PAGE_SIZE - 1))
+    }
+}
+
+impl VirtAddr {
+    pub const fn new(addr: usize) -> Self {
+        Self(addr)
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self(ptr as usize)
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    pub const fn as_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    /// The memory behind us, see [`virt_to_phys`]
+    pub fn to_phys(self) -> PhysAddr {
+        PhysAddr(virt_to_phys(self.0))
+    }
+
+    pub const fn add(self, offset: usize) -> Self {
+        Self(self.0.wrapping_add(offset))
+    }
+
+    /// The index into a level `level` table: 9 bits per level, starting
+    /// at bit 12. VPN[0] = vaddr[20:12], VPN[1] = vaddr[29:21] and so on,
+    /// up to VPN[4] = vaddr[56:48] in Sv57.
+    pub const fn vpn(self, level: usize) -> usize {
+        (self.0 >> (12 + level * 9)) & 0x1ff
+    }
+
+    pub const fn page_offset(self) -> usize {
+        self.0 & (PAGE_SIZE - 1)
+    }
+
+    pub const fn page_align_down(self) -> Self {
+        Self(self.0 & !(PAGE_SIZE - 1))
+    }
+}
+
+impl fmt::Display for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl fmt::Display for VirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl Address for PhysAddr {}
+
+impl Address for VirtAddr {}
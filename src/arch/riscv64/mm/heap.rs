@@ -0,0 +1,379 @@
+//! The part of the kernel heap that grows on demand
+//!
+//! The page allocator's biggest block is 2^`MAX_ORDER` pages, and once
+//! physical memory is fragmented even smaller blocks can run out.
+//! Allocations that [`kmem`](super::kmem) can't get a block for come
+//! from here instead: a range of virtual addresses from [`HEAP_BASE`]
+//! that only needs single frames behind it.
+//!
+//! The heap is a list of regions, in use or free, that covers everything
+//! from [`HEAP_BASE`] up to the break. It grows by mapping frames from the
+//! page allocator above the break. When a free region ends up at the
+//! break, its frames are unmapped and given back. None of this is in the
+//! direct map, so `addr::virt_to_phys` doesn't work on heap memory.
+
+use core::{
+    mem::{align_of, size_of},
+    ptr::null_mut,
+};
+
+use super::{
+    addr::{VirtAddr, HEAP_BASE, HEAP_MAX},
+    kmem, mode,
+    page::{self, align_val, EntryBits, PAGE_ORDER, PAGE_SIZE},
+    slab::Cache,
+};
+use crate::{
+    arch::{csr, ipi, percpu, smp},
+    println,
+    sync::spinlock::SpinLock,
+};
+
+/// A run of pages in the heap, `start..start + pages * PAGE_SIZE`
+struct Region {
+    start: usize,
+    pages: usize,
+    free: bool,
+    next: *mut Region,
+    prev: *mut Region,
+}
+
+impl Region {
+    fn end(&self) -> usize {
+        self.start + self.pages * PAGE_SIZE
+    }
+}
+
+static REGIONS: Cache = Cache::new("heap regions", size_of::<Region>(), align_of::<Region>());
+
+/// The regions in address order, `first` at [`HEAP_BASE`] and `last`
+/// ending at `brk`
+struct Heap {
+    first: *mut Region,
+    last: *mut Region,
+    brk: usize,
+}
+
+// The regions are only reached through the lock
+unsafe impl Send for Heap {}
+
+static HEAP: SpinLock<Heap> = SpinLock::new(Heap {
+    first: null_mut(),
+    last: null_mut(),
+    brk: HEAP_BASE,
+});
+
+fn pages_for(size: usize) -> usize {
+    align_val(size.max(1), PAGE_ORDER) / PAGE_SIZE
+}
+
+fn root() -> &'static mut page::Table {
+    unsafe { kmem::get_page_table().as_mut().unwrap() }
+}
+
+/// Is `ptr` in the heap's range?
+pub fn owns(ptr: *const u8) -> bool {
+    (HEAP_BASE..HEAP_BASE + HEAP_MAX).contains(&(ptr as usize))
+}
+
+/// Allocate `size` bytes aligned to `align`, or at least to a page. The
+/// memory is not zeroed. Returns null if translation isn't on yet, or if
+/// the range or the page allocator run out.
+pub fn alloc(size: usize, align: usize) -> *mut u8 {
+    // Until translation is on nothing we map here can be reached
+    if csr::satp::read() >> mode::SATP_MODE_SHIFT == mode::SATP_MODE_BARE {
+        return null_mut();
+    }
+    let pages = pages_for(size);
+    let align = align.max(PAGE_SIZE);
+    let mut heap = HEAP.lock_irqsave();
+    let mut region = heap.find_free(pages, align);
+    if region.is_null() {
+        // Grow just enough to fit it at the end, with the free region
+        // already there, if any
+        let last = heap.last;
+        let base = if !last.is_null() && unsafe { (*last).free } {
+            unsafe { (*last).start }
+        } else {
+            heap.brk
+        };
+        let end = align_val(base, align.trailing_zeros() as usize) + pages * PAGE_SIZE;
+        let pages = (end - heap.brk) / PAGE_SIZE;
+        if !heap.extend(pages) {
+            return null_mut();
+        }
+        region = heap.last;
+    }
+    unsafe {
+        let start = align_val((*region).start, align.trailing_zeros() as usize);
+        if start > (*region).start {
+            region = heap.split(region, (start - (*region).start) / PAGE_SIZE);
+            if region.is_null() {
+                // Don't keep what we may have just mapped for this
+                heap.trim();
+                return null_mut();
+            }
+        }
+        if (*region).pages > pages && heap.split(region, pages).is_null() {
+            heap.trim();
+            return null_mut();
+        }
+        (*region).free = false;
+        (*region).start as *mut u8
+    }
+}
+
+/// Give back an allocation from [`alloc`]
+pub fn free(ptr: *mut u8) {
+    let mut heap = HEAP.lock_irqsave();
+    let region = heap.find(ptr);
+    unsafe {
+        (*region).free = true;
+        let next = (*region).next;
+        if !next.is_null() && (*next).free {
+            heap.merge(region);
+        }
+        let prev = (*region).prev;
+        if !prev.is_null() && (*prev).free {
+            heap.merge(prev);
+        }
+        // If that left a free region at the end, give it back
+        heap.trim();
+    }
+}
+
+/// How many bytes the allocation at `ptr` has room for
+pub fn capacity(ptr: *const u8) -> usize {
+    let heap = HEAP.lock_irqsave();
+    unsafe { (*heap.find(ptr)).pages * PAGE_SIZE }
+}
+
+/// Grow the allocation at `ptr` in place to `size` bytes, by taking in
+/// the free region after it or, at the end of the heap, by moving the
+/// break. Returns false, and changes nothing, if neither has room.
+pub fn grow(ptr: *mut u8, size: usize) -> bool {
+    let mut heap = HEAP.lock_irqsave();
+    let region = heap.find(ptr);
+    unsafe {
+        let need = pages_for(size).saturating_sub((*region).pages);
+        if need == 0 {
+            return true;
+        }
+        let next = (*region).next;
+        let (free_after, at_end) = if next.is_null() {
+            (0, true)
+        } else if (*next).free {
+            ((*next).pages, (*next).next.is_null())
+        } else {
+            (0, false)
+        };
+        if free_after < need && (!at_end || !heap.extend(need - free_after)) {
+            return false;
+        }
+        let next = (*region).next;
+        if (*next).pages > need && heap.split(next, need).is_null() {
+            heap.trim();
+            return false;
+        }
+        heap.merge(region);
+    }
+    true
+}
+
+pub fn print() {
+    let heap = HEAP.lock_irqsave();
+    let (mut used, mut free) = (0, 0);
+    let mut region = heap.first;
+    while !region.is_null() {
+        unsafe {
+            if (*region).free {
+                free += (*region).pages;
+            } else {
+                used += (*region).pages;
+            }
+            region = (*region).next;
+        }
+    }
+    println!(
+        "heap {:#x} -> {:#x}: {} pages in use, {} free",
+        HEAP_BASE, heap.brk, used, free
+    );
+}
+
+impl Heap {
+    /// The first free region with room for `pages` at `align`
+    fn find_free(&self, pages: usize, align: usize) -> *mut Region {
+        let mut region = self.first;
+        while !region.is_null() {
+            unsafe {
+                let start = align_val((*region).start, align.trailing_zeros() as usize);
+                if (*region).free && start + pages * PAGE_SIZE <= (*region).end() {
+                    return region;
+                }
+                region = (*region).next;
+            }
+        }
+        null_mut()
+    }
+
+    /// The allocation that starts at `ptr`
+    fn find(&self, ptr: *const u8) -> *mut Region {
+        let mut region = self.first;
+        while !region.is_null() {
+            unsafe {
+                if (*region).start == ptr as usize {
+                    assert!(!(*region).free, "{:p} is already free", ptr);
+                    return region;
+                }
+                region = (*region).next;
+            }
+        }
+        panic!("{:p} is not a heap allocation", ptr);
+    }
+
+    /// Cut `region` after its first `pages` pages. The rest becomes a new
+    /// region, in use or free the same as `region`, which is returned. Null
+    /// if there was no memory for it.
+    fn split(&mut self, region: *mut Region, pages: usize) -> *mut Region {
+        let rest = REGIONS.alloc() as *mut Region;
+        if rest.is_null() {
+            return null_mut();
+        }
+        unsafe {
+            rest.write(Region {
+                start: (*region).start + pages * PAGE_SIZE,
+                pages: (*region).pages - pages,
+                free: (*region).free,
+                next: (*region).next,
+                prev: region,
+            });
+            (*region).pages = pages;
+            self.link_after(region, rest);
+        }
+        rest
+    }
+
+    /// Take the region after `region` into it
+    fn merge(&mut self, region: *mut Region) {
+        unsafe {
+            let next = (*region).next;
+            (*region).pages += (*next).pages;
+            (*region).next = (*next).next;
+            if (*next).next.is_null() {
+                self.last = region;
+            } else {
+                (*(*next).next).prev = region;
+            }
+            REGIONS.free(next as *mut u8);
+        }
+    }
+
+    fn link_after(&mut self, region: *mut Region, new: *mut Region) {
+        unsafe {
+            if region.is_null() {
+                self.first = new;
+            } else {
+                (*region).next = new;
+            }
+            match (*new).next.as_mut() {
+                Some(next) => next.prev = new,
+                None => self.last = new,
+            }
+        }
+    }
+
+    /// Map `pages` more pages at the break. They join the last region if
+    /// that is free, or become a free region of their own.
+    fn extend(&mut self, pages: usize) -> bool {
+        let end = self.brk + pages * PAGE_SIZE;
+        if end > HEAP_BASE + HEAP_MAX {
+            return false;
+        }
+        let last = self.last;
+        let region = if !last.is_null() && unsafe { (*last).free } {
+            last
+        } else {
+            let region = REGIONS.alloc() as *mut Region;
+            if region.is_null() {
+                return false;
+            }
+            region
+        };
+        for i in 0..pages {
+            let frame = page::alloc(1);
+            let mapped = !frame.is_null()
+                && page::map(
+                    root(),
+                    VirtAddr::new(self.brk + i * PAGE_SIZE),
+                    VirtAddr::from_ptr(frame).to_phys(),
+                    EntryBits::ReadWrite.val(),
+                    0,
+                );
+            if !mapped {
+                // Memory is about gone, put back what we got so far
+                if !frame.is_null() {
+                    page::dealloc(frame);
+                }
+                unmap(self.brk, i);
+                if region != last {
+                    REGIONS.free(region as *mut u8);
+                }
+                return false;
+            }
+        }
+        // A hart may hold on to a translation that wasn't valid yet
+        unsafe { core::arch::asm!("sfence.vma") };
+        unsafe {
+            if region == last {
+                (*region).pages += pages;
+            } else {
+                region.write(Region {
+                    start: self.brk,
+                    pages,
+                    free: true,
+                    next: null_mut(),
+                    prev: last,
+                });
+                self.link_after(last, region);
+            }
+        }
+        self.brk = end;
+        true
+    }
+
+    /// Give back the free regions at the end of the heap
+    fn trim(&mut self) {
+        while let Some(region) = unsafe { self.last.as_mut() }.filter(|r| r.free) {
+            unmap(region.start, region.pages);
+            self.brk = region.start;
+            self.last = region.prev;
+            match unsafe { self.last.as_mut() } {
+                Some(last) => last.next = null_mut(),
+                None => self.first = null_mut(),
+            }
+            REGIONS.free(region as *mut Region as *mut u8);
+        }
+    }
+}
+
+/// Unmap `pages` pages from `start` and give their frames back
+fn unmap(start: usize, pages: usize) {
+    let root = root();
+    for i in 0..pages {
+        let vaddr = VirtAddr::new(start + i * PAGE_SIZE);
+        if let Some(frame) = page::unmap_page(root, vaddr) {
+            page::dealloc(frame.to_virt().as_ptr());
+        }
+    }
+    flush_tlbs();
+}
+
+// Forget the pages we unmapped everywhere they may be cached
+fn flush_tlbs() {
+    unsafe { core::arch::asm!("sfence.vma") };
+    let online = smp::online_harts();
+    // Before the secondaries are up `tp` may not even be set yet
+    if online.count_ones() > 1 {
+        ipi::tlb_shootdown(online & !(1 << percpu::hartid()));
+    }
+}
@@ -0,0 +1,230 @@
+use super::{
+	heap,
+	page::{self, align_val, zalloc, Table, PAGE_ORDER, PAGE_SIZE},
+	slab::{self, Cache},
+};
+use core::ptr::null_mut;
+
+// The kmalloc size classes, 16 bytes to 2 KiB. Each one is a slab
+// cache whose objects are aligned to their size. Anything bigger gets
+// whole pages straight from the page allocator, or failing that, from
+// the heap.
+const MIN_CLASS_ORDER: usize = 4;
+const MAX_CLASS_ORDER: usize = 11;
+pub const MAX_CLASS_SIZE: usize = 1 << MAX_CLASS_ORDER;
+
+static CACHES: [Cache; MAX_CLASS_ORDER - MIN_CLASS_ORDER + 1] = [
+	Cache::new("kmalloc-16", 16, 16),
+	Cache::new("kmalloc-32", 32, 32),
+	Cache::new("kmalloc-64", 64, 64),
+	Cache::new("kmalloc-128", 128, 128),
+	Cache::new("kmalloc-256", 256, 256),
+	Cache::new("kmalloc-512", 512, 512),
+	Cache::new("kmalloc-1024", 1024, 1024),
+	Cache::new("kmalloc-2048", 2048, 2048),
+];
+
+static mut KMEM_PAGE_TABLE: *mut Table = null_mut();
+
+pub fn get_page_table() -> *mut Table {
+	unsafe { KMEM_PAGE_TABLE as *mut Table }
+}
+
+/// Initialize kernel's memory
+/// This is not to be used to allocate memory
+/// for user processes. If that's the case, use
+/// alloc/dealloc from the page crate.
+pub fn init() {
+	unsafe {
+		KMEM_PAGE_TABLE = zalloc(1) as *mut Table;
+	}
+}
+
+/// The cache kmalloc uses for `sz` bytes, None if it takes whole pages
+fn size_class(sz: usize) -> Option<&'static Cache> {
+	if sz > MAX_CLASS_SIZE {
+		return None;
+	}
+	let order = sz.next_power_of_two().trailing_zeros() as usize;
+	Some(&CACHES[order.max(MIN_CLASS_ORDER) - MIN_CLASS_ORDER])
+}
+
+// The size classes are aligned to their size, and blocks of pages to
+// theirs, so asking for at least `align` bytes is enough to get that
+// alignment. This holds up to the biggest block the page allocator has.
+fn aligned_size(sz: usize, align: usize) -> usize {
+	debug_assert!(align.is_power_of_two());
+	sz.max(align)
+}
+
+// Whole pages for what the size classes don't cover. Past the biggest
+// block, or when no block that big is free, the heap can still do it
+// with single frames.
+fn large_alloc(sz: usize, align: usize) -> *mut u8 {
+	let pages = align_val(aligned_size(sz, align), PAGE_ORDER) / PAGE_SIZE;
+	let ret = page::alloc(pages);
+	if !ret.is_null() {
+		return ret;
+	}
+	heap::alloc(sz, align)
+}
+
+/// Allocate `sz` bytes aligned to `align`, which must be a power of two
+pub fn kmalloc_aligned(sz: usize, align: usize) -> *mut u8 {
+	match size_class(aligned_size(sz, align)) {
+		Some(cache) => cache.alloc(),
+		None => large_alloc(sz, align),
+	}
+}
+
+/// Allocate `sz` zeroed bytes aligned to `align`
+pub fn kzmalloc_aligned(sz: usize, align: usize) -> *mut u8 {
+	match size_class(aligned_size(sz, align)) {
+		Some(cache) => cache.zalloc(),
+		None => {
+			let ret = large_alloc(sz, align);
+			if !ret.is_null() {
+				unsafe { ret.write_bytes(0, sz) };
+			}
+			ret
+		},
+	}
+}
+
+/// Allocate sub-page level allocation based on bytes and zero the memory
+pub fn kzmalloc(sz: usize) -> *mut u8 {
+	kzmalloc_aligned(sz, 1)
+}
+
+/// Allocate sub-page level allocation based on bytes
+pub fn kmalloc(sz: usize) -> *mut u8 {
+	kmalloc_aligned(sz, 1)
+}
+
+// How many bytes the allocation at `ptr` really has room for
+fn capacity(ptr: *const u8) -> usize {
+	if heap::owns(ptr) {
+		heap::capacity(ptr)
+	}
+	else if slab::owns(ptr) {
+		slab::cache_of(ptr).size()
+	}
+	else {
+		page::allocation_pages(ptr) * PAGE_SIZE
+	}
+}
+
+/// Resize the allocation at `ptr` to `sz` bytes aligned to `align`,
+/// keeping its contents. It stays where it is if it has room or, for
+/// whole pages and the heap, if the memory after it is free to take in.
+/// Otherwise it moves, and the old allocation is freed. On failure null
+/// is returned and `ptr` is left alone.
+///
+/// # Safety
+///
+/// `ptr` must be null or an allocation from this module that is still
+/// live.
+pub unsafe fn krealloc(ptr: *mut u8, sz: usize, align: usize) -> *mut u8 {
+	if ptr.is_null() {
+		return kmalloc_aligned(sz, align);
+	}
+	let want = aligned_size(sz, align);
+	let have = capacity(ptr);
+	if want <= have {
+		return ptr;
+	}
+	let grown = if heap::owns(ptr) {
+		heap::grow(ptr, want)
+	}
+	else {
+		!slab::owns(ptr) && page::grow(ptr, align_val(want, PAGE_ORDER) / PAGE_SIZE)
+	};
+	if grown {
+		return ptr;
+	}
+	let new = kmalloc_aligned(sz, align);
+	if !new.is_null() {
+		new.copy_from_nonoverlapping(ptr, have);
+		kfree(ptr);
+	}
+	new
+}
+
+/// Free a sub-page level allocation
+pub fn kfree(ptr: *mut u8) {
+	if ptr.is_null() {
+		return;
+	}
+	if heap::owns(ptr) {
+		heap::free(ptr);
+	}
+	else if slab::owns(ptr) {
+		slab::free(ptr);
+	}
+	else {
+		page::dealloc(ptr);
+	}
+}
+
+/// For debugging purposes, print the kmalloc caches and the heap
+pub fn print_table() {
+	for cache in CACHES.iter() {
+		cache.print();
+	}
+	heap::print();
+}
+
+// ///////////////////////////////////
+// / GLOBAL ALLOCATOR
+// ///////////////////////////////////
+
+// The global allocator allows us to use the data structures
+// in the core library, such as a linked list or B-tree.
+use core::alloc::{GlobalAlloc, Layout};
+
+// The global allocator is a static constant to a global allocator
+// structure. We don't need any members because we're using this
+// structure just to implement alloc and dealloc.
+struct OsGlobalAlloc;
+
+unsafe impl GlobalAlloc for OsGlobalAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		// Small sizes come from the slab caches, the rest
+		// is whole pages.
+		kmalloc_aligned(layout.size(), layout.align())
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		// alloc() doesn't zero, so this is the one place
+		// the memory gets zeroed.
+		kzmalloc_aligned(layout.size(), layout.align())
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		krealloc(ptr, new_size, layout.align())
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+		// We ignore layout since the page allocator knows
+		// whether ptr is a slab object or a block of pages.
+		kfree(ptr);
+	}
+}
+
+#[global_allocator]
+/// Technically, we don't need the {} at the end, but it
+/// reveals that we're creating a new structure and not just
+/// copying a value.
+static GA: OsGlobalAlloc = OsGlobalAlloc {};
+
+#[alloc_error_handler]
+/// If for some reason alloc() in the global allocator gets null_mut(),
+/// then we come here. This is a divergent function, so we call panic to
+/// let the tester know what's going on.
+pub fn alloc_error(l: Layout) -> ! {
+	panic!(
+	       "Allocator failed to allocate {} bytes with {}-byte alignment.",
+	       l.size(),
+	       l.align()
+	);
+}
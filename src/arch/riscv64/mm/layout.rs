@@ -0,0 +1,124 @@
+//! Where the linker put the kernel
+//!
+//! The linker scripts in `lds/` mark the start and end of every section
+//! with a symbol, which `boot/asm/mem.s` exports as variables we can
+//! read. [`KernelLayout`] is the only thing that declares them.
+
+use core::fmt;
+
+use super::{addr::VirtAddr, physmap::PhysRange};
+
+extern "C" {
+    static TEXT_START: usize;
+    static TEXT_END: usize;
+    static RODATA_START: usize;
+    static DATA_START: usize;
+    static DATA_END: usize;
+    static BSS_START: usize;
+    static BSS_END: usize;
+    static KERNEL_STACK_START: usize;
+    static KERNEL_STACK_END: usize;
+    static HEAP_START: usize;
+    static HEAP_SIZE: usize;
+}
+
+/// A part of the kernel image, at the virtual addresses `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+}
+
+impl Section {
+    fn new(start: usize, end: usize) -> Self {
+        Self {
+            start: VirtAddr::new(start),
+            end: VirtAddr::new(end),
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.end.as_usize().saturating_sub(self.start.as_usize())
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// The physical memory behind the section
+    pub fn phys(&self) -> PhysRange {
+        PhysRange::new(
+            self.start.to_phys().as_usize(),
+            self.end.to_phys().as_usize(),
+        )
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.start, self.end)
+    }
+}
+
+/// The kernel image, section by section
+#[derive(Debug, Clone, Copy)]
+pub struct KernelLayout {
+    pub text: Section,
+    /// Everything from `.rodata` up to `.data`, which takes in the symbol
+    /// table and the kernel parameters
+    pub rodata: Section,
+    pub data: Section,
+    pub bss: Section,
+    /// The boot stacks, a slice per hart
+    pub stack: Section,
+    /// The rest of RAM after the image, as far as the linker script
+    /// knows. The device tree knows better.
+    pub heap: Section,
+}
+
+impl KernelLayout {
+    pub fn get() -> Self {
+        unsafe {
+            Self {
+                text: Section::new(TEXT_START, TEXT_END),
+                rodata: Section::new(RODATA_START, DATA_START),
+                data: Section::new(DATA_START, DATA_END),
+                bss: Section::new(BSS_START, BSS_END),
+                stack: Section::new(KERNEL_STACK_START, KERNEL_STACK_END),
+                heap: Section::new(HEAP_START, HEAP_START + HEAP_SIZE),
+            }
+        }
+    }
+
+    /// From the start of `.text` to the end of the boot stacks
+    pub fn image(&self) -> Section {
+        Section {
+            start: self.text.start,
+            end: self.stack.end,
+        }
+    }
+
+    /// The sections of the image, in address order
+    pub fn sections(&self) -> [(&'static str, Section); 5] {
+        [
+            ("text", self.text),
+            ("rodata", self.rodata),
+            ("data", self.data),
+            ("bss", self.bss),
+            ("stack", self.stack),
+        ]
+    }
+}
+
+impl fmt::Display for KernelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, section) in self.sections() {
+            writeln!(f, "  {:<7}{} ({} KiB)", name, section, section.len() / 1024)?;
+        }
+        write!(f, "  {:<7}{}", "heap", self.heap)
+    }
+}
@@ -1,47 +1,274 @@
-#[cfg(feature = "riscv-sv39")]
-pub mod sv39;
+//! Memory management
+//!
+//! Physical memory comes from the device tree ([`physmap`]) and is handed
+//! out in power-of-two blocks of pages by the buddy allocator in
+//! [`page`], which also has the page table code. [`kmem`] is the kernel
+//! heap: small allocations come from the per-size caches in [`slab`], and
+//! the rest take whole pages, or virtual memory in [`heap`] that grows as
+//! needed when the page allocator has no block big enough. The kernel
+//! itself is described by [`layout`], and [`addr`] has the address types
+//! and the conversions between the kernel image, the direct map and
+//! physical memory. [`init`] ties it all together and turns on
+//! translation.
 
-use core::ptr::{null, null_mut};
+use self::{
+    addr::{PhysAddr, VirtAddr},
+    layout::KernelLayout,
+    physmap::PhysRange,
+};
+use super::{
+    boot, csr,
+    trap::fault::{self, Access},
+};
+use crate::{
+    drivers::{plic, serial},
+    println,
+};
 
-#[cfg(feature = "riscv-sv39")]
-pub use sv39::*;
+pub mod addr;
+pub mod heap;
+pub mod kmem;
+pub mod layout;
+pub mod mode;
+pub mod page;
+pub mod physmap;
+pub mod slab;
 
-use crate::{println, arch::mm::constants::{heap_size, heap_page_count}};
+pub use page::PAGE_SIZE;
 
-use self::table::PageTable;
+pub fn init() {
+    let mut map = physmap::PhysMemoryMap::from_firmware(crate::fdt::get());
+    println!("Physical memory:\n{}", map);
+    page::init(&mut map);
+    physmap::set_memory_map(map);
 
+    println!("Paging mode: {}", mode::probe());
+    kmem::init();
+    let root = unsafe { kmem::get_page_table().as_mut().unwrap() };
+    let layout = KernelLayout::get();
+    println!("Kernel image:\n{}", layout);
+    println!(
+        "Kernel heap: {:#x} -> {:#x}",
+        addr::HEAP_BASE,
+        addr::HEAP_BASE + addr::HEAP_MAX
+    );
 
-mod constants;
-
-pub mod allocator;
+    // The direct map: all the RAM the page allocator hands out (the kernel
+    // heap and the page tables themselves included), the page descriptors
+    // and the device tree, which we keep reading after boot
+    let mut direct = physmap::memory_map().clone();
+    direct.add(page::descriptors());
+    if let Some(dtb) = dtb_range() {
+        direct.add(dtb);
+    }
+    for &range in direct.ranges() {
+        map_ram(root, range, page::EntryBits::ReadWrite.val());
+    }
+    // We put the ROdata section into the text section, so they can
+    // potentially overlap however, we only care that it's read only.
+    for (name, section) in layout.sections() {
+        let bits = match name {
+            "text" | "rodata" => page::EntryBits::ReadExecute,
+            _ => page::EntryBits::ReadWrite,
+        };
+        map_range(root, section.start, section.end, bits.val());
+    }
 
+    // UART
+    let uart = VirtAddr::new(serial::port().base());
+    map_range(root, uart, uart.add(1), page::EntryBits::ReadWrite.val());
+    // PLIC
+    let (plic_start, plic_end) = plic::PLIC.mmio();
+    map_range(
+        root,
+        VirtAddr::new(plic_start),
+        VirtAddr::new(plic_end),
+        page::EntryBits::ReadWrite.val(),
+    );
+    if serial::debug() {
+        kmem::print_table();
+    }
 
-static mut KMEM_PAGE_TABLE: *mut PageTable = null_mut();
+    activate(root, KERNEL_ASID);
+}
 
+/// Where the device tree is, if we have one
+fn dtb_range() -> Option<PhysRange> {
+    let fdt = crate::fdt::get()?;
+    let start = boot::dtb()?;
+    Some(PhysRange::new(start, start + fdt.total_size()))
+}
 
+/// Something that has to stay reachable once translation is on, and how
+struct Region {
+    name: &'static str,
+    start: VirtAddr,
+    end: VirtAddr,
+    access: Access,
+}
 
-// TODO use err here
-pub fn initialize() -> core::result::Result<(), ()> {
-    sv39::initialize();
+/// Everything the kernel touches, whatever hart it runs on
+fn kernel_regions() -> impl Iterator<Item = Region> {
+    let region = |name, start, end, access| Region {
+        name,
+        start: VirtAddr::new(start),
+        end: VirtAddr::new(end),
+        access,
+    };
+    let direct = move |name, range: PhysRange, access| Region {
+        name,
+        start: PhysAddr::new(range.start).to_virt(),
+        end: PhysAddr::new(range.end).to_virt(),
+        access,
+    };
+    let image = KernelLayout::get()
+        .sections()
+        .map(|(name, section)| Region {
+            name,
+            start: section.start,
+            end: section.end,
+            access: match name {
+                "text" => Access::Fetch,
+                "rodata" => Access::Load,
+                _ => Access::Store,
+            },
+        });
+    let uart = serial::port().base();
+    let (plic_start, plic_end) = plic::PLIC.mmio();
+    let devices = [
+        direct("page descriptors", page::descriptors(), Access::Store),
+        region("UART", uart, uart + 8, Access::Store),
+        region("PLIC", plic_start, plic_end, Access::Store),
+    ];
+    let dtb = dtb_range().map(|range| direct("device tree", range, Access::Load));
+    let ram = physmap::memory_map()
+        .ranges()
+        .iter()
+        .map(move |&range| direct("heap", range, Access::Store));
+    image.into_iter().chain(devices).chain(dtb).chain(ram)
+}
 
+/// Walk `root` for every page of every kernel region, and panic with the
+/// first one that would fault, while we can still print about it.
+fn check_mappings(root: &page::Table) {
+    let mode = mode::current();
+    for region in kernel_regions() {
+        let mut vaddr = region.start.page_align_down();
+        while vaddr < region.end {
+            if let Err(reason) = fault::check(root, vaddr, region.access) {
+                panic!(
+                    "{}: {} of {} at {} ({} -> {}) would fault: {}",
+                    mode, region.access, region.name, vaddr, region.start, region.end, reason
+                );
+            }
+            let expected = vaddr.to_phys();
+            if page::translate(root, vaddr) != Some(expected) {
+                panic!(
+                    "{}: {} at {} ({} -> {}) is not mapped to {}",
+                    mode, region.name, vaddr, region.start, region.end, expected
+                );
+            }
+            vaddr = vaddr.add(PAGE_SIZE);
+        }
+    }
+}
 
-    let mut allocator = allocator::MaqAllocator::new();
-    unsafe {
-        KMEM_PAGE_TABLE = allocator.zalloc(1).unwrap() as *mut PageTable;
+/// Read, and where we may, write back the first word of every kernel
+/// region. Anything that isn't mapped after all faults here and gets
+/// explained by the page fault handler.
+fn touch_regions() {
+    for region in kernel_regions() {
+        let addr: *mut u8 = region.start.as_ptr();
+        unsafe {
+            match (region.access, region.name) {
+                (Access::Fetch | Access::Load, _) => {
+                    addr.read_volatile();
+                }
+                // Reading most device registers has side effects. The
+                // UART's line status (offset 5) and the PLIC's priority
+                // for source 0 have none.
+                (_, "UART") => {
+                    addr.add(5).read_volatile();
+                }
+                (_, "PLIC") => {
+                    (addr as *mut u32).read_volatile();
+                }
+                (Access::Store, _) => addr.write_volatile(addr.read_volatile()),
+            }
+        }
     }
+}
+
+/// ASID the kernel's address space runs under
+pub const KERNEL_ASID: u16 = 0;
 
+const SATP_ASID_SHIFT: usize = 44;
+const SATP_ASID_MASK: usize = 0xffff << SATP_ASID_SHIFT;
 
-    println!("Capacity for {} heap pages", heap_page_count());
+/// Turn on translation in [`mode::current`] on this hart, with `root` as
+/// the root table and address space `asid`.
+///
+/// `root` has to map everything the kernel uses, which is checked before
+/// the switch. Afterwards every region is touched once, so a mapping
+/// that is wrong all the same faults right here.
+pub fn activate(root: &page::Table, asid: u16) {
+    check_mappings(root);
 
-    // Sets the intial size of the heap.
-    allocator.setup_heap(64);
-    // Allocate the pages for the heap
+    let mode = mode::current();
+    let satp = mode.satp() << mode::SATP_MODE_SHIFT
+        | (asid as usize) << SATP_ASID_SHIFT
+        | VirtAddr::from_ptr(root).to_phys().as_usize() >> 12;
     unsafe {
-        //println!("Got Heap pointer at: {:#x?}", p);
+        csr::satp::write(satp);
+        core::arch::asm!("sfence.vma");
     }
-
-
-    Ok(())
+    // Writing an unsupported mode leaves satp alone entirely, and ASID
+    // bits the hart doesn't have read back as 0
+    let active = csr::satp::read();
+    if active & !SATP_ASID_MASK != satp & !SATP_ASID_MASK {
+        panic!(
+            "{}: satp reads back {:#018x} after writing {:#018x}, is {} supported?",
+            mode, active, satp, mode
+        );
+    }
+    if active != satp {
+        println!(
+            "{}: hart doesn't have ASID {}, running as ASID {}",
+            mode,
+            asid,
+            (active & SATP_ASID_MASK) >> SATP_ASID_SHIFT
+        );
+    }
+    touch_regions();
+    println!("{} enabled, satp {:#018x}", mode, active);
 }
 
+/// Map the physical `range` into the direct map, using 2 MiB pages for
+/// the parts of it that cover one. Only for RAM nothing else is mapped
+/// in, a 2 MiB page would replace any table under it.
+pub fn map_ram(root: &mut page::Table, range: PhysRange, bits: i64) {
+    const MEGAPAGE: usize = 1 << 21;
+    let mut paddr = PhysAddr::new(range.start).page_align_down();
+    while paddr.as_usize() < range.end {
+        if paddr.as_usize() % MEGAPAGE == 0 && paddr.as_usize() + MEGAPAGE <= range.end {
+            let mapped = page::map(root, paddr.to_virt(), paddr, bits, 1);
+            assert!(mapped, "out of memory for the page tables");
+            paddr = paddr.add(MEGAPAGE);
+        } else {
+            let mapped = page::map(root, paddr.to_virt(), paddr, bits, 0);
+            assert!(mapped, "out of memory for the page tables");
+            paddr = paddr.add(PAGE_SIZE);
+        }
+    }
+}
 
+/// Map the kernel addresses `start..end`, in the image or the direct map,
+/// to the physical memory behind them, in 4 KiB pages
+pub fn map_range(root: &mut page::Table, start: VirtAddr, end: VirtAddr, bits: i64) {
+    let mut vaddr = start.page_align_down();
+    while vaddr < end {
+        let mapped = page::map(root, vaddr, vaddr.to_phys(), bits, 0);
+        assert!(mapped, "out of memory for the page tables");
+        vaddr = vaddr.add(PAGE_SIZE);
+    }
+}
@@ -0,0 +1,166 @@
+//! Which paging mode the kernel's tables are built for
+//!
+//! Sv39, Sv48 and Sv57 share the entry format and differ only in the
+//! number of table levels, 3, 4 or 5, each translating 9 more bits of
+//! virtual address. Sv48 and Sv57 are built in with the `riscv-sv48` and
+//! `riscv-sv57` features. Which mode we use is decided once at boot by
+//! [`probe`]: a hart ignores writes of a mode it doesn't implement to
+//! `satp`, so we try the widest one first and fall back to Sv39.
+//!
+//! The higher half addresses in `mm::addr` are canonical in every
+//! mode, so nothing else has to know which one we picked.
+
+use core::fmt;
+
+use super::{
+    addr,
+    page::{self, EntryBits, Table},
+};
+use crate::arch::csr;
+
+pub const SATP_MODE_SHIFT: usize = 60;
+pub const SATP_MODE_BARE: usize = 0;
+pub const SATP_PPN_MASK: usize = (1 << 44) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl Mode {
+    /// `satp.MODE` for this mode
+    pub const fn satp(self) -> usize {
+        match self {
+            Self::Sv39 => 8,
+            Self::Sv48 => 9,
+            Self::Sv57 => 10,
+        }
+    }
+
+    pub const fn from_satp(mode: usize) -> Option<Self> {
+        match mode {
+            8 => Some(Self::Sv39),
+            9 => Some(Self::Sv48),
+            10 => Some(Self::Sv57),
+            _ => None,
+        }
+    }
+
+    pub const fn levels(self) -> usize {
+        match self {
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+            Self::Sv57 => 5,
+        }
+    }
+
+    /// The level translation starts at, i.e. the root table's
+    pub const fn root_level(self) -> usize {
+        self.levels() - 1
+    }
+
+    /// How many bits of a virtual address get translated. The bits above
+    /// have to be copies of the top one.
+    pub const fn va_bits(self) -> usize {
+        12 + 9 * self.levels()
+    }
+
+    pub const fn is_canonical(self, vaddr: usize) -> bool {
+        let top = (vaddr as isize) >> (self.va_bits() - 1);
+        top == 0 || top == -1
+    }
+
+    /// The modes we were built with, widest first
+    fn built_in() -> impl Iterator<Item = Self> {
+        [
+            (cfg!(feature = "riscv-sv57"), Self::Sv57),
+            (cfg!(feature = "riscv-sv48"), Self::Sv48),
+            (true, Self::Sv39),
+        ]
+        .into_iter()
+        .filter_map(|(built, mode)| built.then_some(mode))
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+static mut MODE: Mode = Mode::Sv39;
+
+/// The mode every kernel table is built for
+pub fn current() -> Mode {
+    unsafe { MODE }
+}
+
+/// Pick the widest mode both we and the hart support, and build every
+/// table from now on for it. Boot hart only, once the page allocator is
+/// up and before the first table is built.
+pub fn probe() -> Mode {
+    let mode = Mode::built_in()
+        .find(|&mode| mode == Mode::Sv39 || supported(mode))
+        .unwrap_or(Mode::Sv39);
+    unsafe { MODE = mode };
+    mode
+}
+
+/// Does the hart take `mode` in `satp`?
+///
+/// A write that takes effect turns translation on right away, so it has
+/// to be with a table that maps everything the way it is mapped now.
+/// That is the identity map while paging is off and whatever Sv39 table
+/// we run on otherwise. We put it at the bottom of a stack of tables
+/// whose first and last entries point one level down, which keeps both
+/// halves of the Sv39 address space where they were.
+fn supported(mode: Mode) -> bool {
+    let old = csr::satp::read();
+    let mut tables = [core::ptr::null_mut::<Table>(); 3];
+
+    let sv39_root = if old >> SATP_MODE_SHIFT == SATP_MODE_BARE {
+        // 512 GiB of identity map in 1 GiB pages
+        let table = page::zalloc(1) as *mut Table;
+        for (i, entry) in unsafe { (*table).entries.iter_mut() }.enumerate() {
+            entry.set_entry(
+                (i << 28) as i64
+                    | EntryBits::ReadWriteExecute.val()
+                    | EntryBits::Valid.val()
+                    | EntryBits::Access.val()
+                    | EntryBits::Dirty.val(),
+            );
+        }
+        tables[0] = table;
+        addr::virt_to_phys(table as usize)
+    } else {
+        (old & SATP_PPN_MASK) << 12
+    };
+
+    let mut below = sv39_root;
+    for table in tables.iter_mut().skip(1).take(mode.levels() - 3) {
+        *table = page::zalloc(1) as *mut Table;
+        let entries = unsafe { &mut (**table).entries };
+        let branch = (below >> 2) as i64 | EntryBits::Valid.val();
+        entries[0].set_entry(branch);
+        entries[Table::len() - 1].set_entry(branch);
+        below = addr::virt_to_phys(*table as usize);
+    }
+
+    let satp = mode.satp() << SATP_MODE_SHIFT | below >> 12;
+    let taken = unsafe {
+        core::arch::asm!("sfence.vma");
+        csr::satp::write(satp);
+        core::arch::asm!("sfence.vma");
+        let taken = csr::satp::read() >> SATP_MODE_SHIFT == mode.satp();
+        csr::satp::write(old);
+        core::arch::asm!("sfence.vma");
+        taken
+    };
+
+    for table in tables.into_iter().filter(|t| !t.is_null()) {
+        page::dealloc(table as *mut u8);
+    }
+    taken
+}
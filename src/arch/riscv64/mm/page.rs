@@ -0,0 +1,797 @@
+use core::{mem::size_of, ptr::null_mut};
+
+use super::{
+	addr::{self, PhysAddr, VirtAddr},
+	mode,
+	physmap::{PhysMemoryMap, PhysRange},
+};
+use crate::{println, print, sync::spinlock::SpinLock};
+
+// ////////////////////////////////
+// // Allocation routines
+// ////////////////////////////////
+
+// Pages are handed out by a buddy allocator: memory is split into
+// blocks of 2^order pages, aligned to their size, and a free block of
+// order k is kept on FREE_LISTS[k]. Allocating splits the smallest free
+// block that is big enough, freeing merges a block with its buddy (the
+// other half of the block of order k + 1) for as long as that is free.
+// Both take at most MAX_ORDER steps.
+//
+// One Page structure for every page from ALLOC_START up to the end of
+// the highest usable range. Pages in the holes between ranges are
+// marked Reserved and never handed out. ALLOC_START is a physical
+// address, the pages are handed out at their address in the direct
+// map (see mm::addr).
+static mut PAGES: *mut Page = null_mut();
+static mut NUM_PAGES: usize = 0;
+// We will use ALLOC_START to mark the start of the actual
+// memory we can dish out.
+static mut ALLOC_START: usize = 0;
+pub const PAGE_ORDER: usize = 12;
+pub const PAGE_SIZE: usize = 1 << 12;
+
+/// The largest block is 2^MAX_ORDER pages (8 MiB), which is what the
+/// kernel heap starts out with.
+pub const MAX_ORDER: usize = 11;
+
+// The free blocks of each order. The links live in the first bytes of
+// the free blocks themselves, at their direct map address. Whoever
+// holds the lock may also change the Page structures.
+struct FreeLists([*mut FreeBlock; MAX_ORDER + 1]);
+
+// The lists are only reached through the lock
+unsafe impl Send for FreeLists {}
+
+static FREE_LISTS: SpinLock<FreeLists> = SpinLock::new(FreeLists([null_mut(); MAX_ORDER + 1]));
+
+struct FreeBlock {
+	next: *mut FreeBlock,
+	prev: *mut FreeBlock,
+}
+
+/// Align (set to a multiple of some power of two)
+/// This takes an order which is the exponent to 2^order
+/// Therefore, all alignments must be made as a power of two.
+/// This function always rounds up.
+pub const fn align_val(val: usize, order: usize) -> usize {
+	let o = (1usize << order) - 1;
+	(val + o) & !o
+}
+
+/// The order of the smallest block that holds `pages` pages
+pub const fn order_for(pages: usize) -> usize {
+	pages.next_power_of_two().trailing_zeros() as usize
+}
+
+#[repr(u8)]
+pub enum PageBits {
+	Empty = 0,
+	Taken = 1 << 0,
+	// The first page of a block, allocated or free. Its Page
+	// structure holds the block's order.
+	Head = 1 << 1,
+	// Not RAM we may use. Always set together with Taken.
+	Reserved = 1 << 2,
+	// An allocated block the slab allocator cuts into objects. Only
+	// set on the head.
+	Slab = 1 << 3,
+}
+
+impl PageBits {
+	// We convert PageBits to a u8 a lot, so this is
+	// for convenience.
+	pub fn val(self) -> u8 {
+		self as u8
+	}
+}
+
+// Each page is described by the Page structure. Linux does this
+// as well, where each 4096-byte chunk of memory has a structure
+// associated with it. However, there structure is much larger.
+pub struct Page {
+	flags: u8,
+	order: u8,
+}
+
+impl Page {
+	pub fn is_head(&self) -> bool {
+		self.flags & PageBits::Head.val() != 0
+	}
+
+	// If the page is marked as being taken (allocated), then
+	// this function returns true. Otherwise, it returns false.
+	pub fn is_taken(&self) -> bool {
+		self.flags & PageBits::Taken.val() != 0
+	}
+
+	pub fn is_reserved(&self) -> bool {
+		self.flags & PageBits::Reserved.val() != 0
+	}
+
+	pub fn is_slab(&self) -> bool {
+		self.flags & PageBits::Slab.val() != 0
+	}
+
+	// This is the opposite of is_taken().
+	pub fn is_free(&self) -> bool {
+		!self.is_taken()
+	}
+
+	// The order of the block this page is the head of
+	pub fn order(&self) -> usize {
+		self.order as usize
+	}
+
+	// Clear the Page structure and all associated allocations.
+	pub fn clear(&mut self) {
+		self.flags = PageBits::Empty.val();
+		self.order = 0;
+	}
+
+	// Set a certain flag. We ran into trouble here since PageBits
+	// is an enumeration and we haven't implemented the BitOr Trait
+	// on it.
+	pub fn set_flag(&mut self, flag: PageBits) {
+		self.flags |= flag.val();
+	}
+
+	pub fn clear_flag(&mut self, flag: PageBits) {
+		self.flags &= !(flag.val());
+	}
+}
+
+/// Initialize the allocation system. There are several ways that we can
+/// implement the page allocator:
+/// 1. Free list (singly linked list where it starts at the first free
+/// allocation) 2. Bookkeeping list (structure contains a taken and length)
+/// 3. Allocate one Page structure per 4096 bytes
+/// 4. Buddy allocator (this is what we do, on top of 3.)
+///
+/// Every page in `map` can be allocated, except for the ones the Page
+/// structures themselves end up in, which are taken out of `map`.
+pub fn init(map: &mut PhysMemoryMap) {
+	let span = map.span().expect("no usable memory");
+	let num_pages = span.len() >> PAGE_ORDER;
+	// Take whole pages, so that what is left of the map still starts
+	// and ends on page boundaries.
+	let descriptors = map
+		.take(align_val(num_pages * size_of::<Page>(), PAGE_ORDER), PAGE_SIZE)
+		.expect("no room for the page descriptors");
+	let mut lists = FREE_LISTS.lock_irqsave();
+	unsafe {
+		PAGES = addr::phys_to_virt(descriptors) as *mut Page;
+		NUM_PAGES = num_pages;
+		ALLOC_START = span.start;
+		// Start with every page reserved, then free what the map
+		// says we may use.
+		for i in 0..num_pages {
+			let page = &mut *PAGES.add(i);
+			page.flags = PageBits::Taken.val() | PageBits::Reserved.val();
+			page.order = 0;
+		}
+		for range in map.ranges() {
+			let first = (range.start - ALLOC_START) / PAGE_SIZE;
+			let end = first + range.len() / PAGE_SIZE;
+			// Free blocks get their links written into them, which
+			// would clobber any Page structures in there.
+			let freed = PhysRange::new(ALLOC_START + first * PAGE_SIZE,
+			                           ALLOC_START + end * PAGE_SIZE);
+			debug_assert!(
+			              !freed.overlaps(&self::descriptors()),
+			              "freeing {} would free the page descriptors at {}",
+			              freed,
+			              self::descriptors()
+			);
+			for i in first..end {
+				(*PAGES.add(i)).clear();
+			}
+			// Hand the range over in the biggest aligned blocks that
+			// fit, freeing merges whatever can be merged.
+			let mut i = first;
+			while i < end {
+				let pfn = (ALLOC_START >> PAGE_ORDER) + i;
+				let mut order = MAX_ORDER.min(pfn.trailing_zeros() as usize);
+				while i + (1 << order) > end {
+					order -= 1;
+				}
+				lists.free_block(i, order);
+				i += 1 << order;
+			}
+		}
+	}
+}
+
+/// Where the Page structures live
+pub fn descriptors() -> PhysRange {
+	unsafe {
+		let start = addr::virt_to_phys(PAGES as usize);
+		PhysRange::new(start, start + NUM_PAGES * size_of::<Page>())
+	}
+}
+
+// The index of the buddy of the order `order` block at `idx`, if there
+// is a Page structure for it. Blocks are aligned by their physical
+// address, so that is what the buddy is worked out from.
+unsafe fn buddy_of(idx: usize, order: usize) -> Option<usize> {
+	let base = ALLOC_START >> PAGE_ORDER;
+	let buddy = ((base + idx) ^ (1 << order)).checked_sub(base)?;
+	(buddy < NUM_PAGES).then_some(buddy)
+}
+
+unsafe fn block_addr(idx: usize) -> *mut FreeBlock {
+	addr::phys_to_virt(ALLOC_START + idx * PAGE_SIZE) as *mut FreeBlock
+}
+
+impl FreeLists {
+	// Put the free block at `idx` on the free list for `order`
+	unsafe fn push(&mut self, idx: usize, order: usize) {
+		let page = &mut *PAGES.add(idx);
+		page.flags = PageBits::Head.val();
+		page.order = order as u8;
+		let block = block_addr(idx);
+		let head = self.0[order];
+		(*block).next = head;
+		(*block).prev = null_mut();
+		if !head.is_null() {
+			(*head).prev = block;
+		}
+		self.0[order] = block;
+	}
+
+	// Take the free block at `idx` off the free list for `order`
+	unsafe fn remove(&mut self, idx: usize, order: usize) {
+		let block = block_addr(idx);
+		let (next, prev) = ((*block).next, (*block).prev);
+		if !next.is_null() {
+			(*next).prev = prev;
+		}
+		if prev.is_null() {
+			self.0[order] = next;
+		}
+		else {
+			(*prev).next = next;
+		}
+		(*PAGES.add(idx)).clear();
+	}
+
+	// Free the order `order` block at `idx`, merging it with its buddy
+	// for as long as the buddy is a free block of the same order.
+	unsafe fn free_block(&mut self, mut idx: usize, mut order: usize) {
+		while order < MAX_ORDER {
+			let Some(buddy) = buddy_of(idx, order)
+			else {
+				break;
+			};
+			let page = &*PAGES.add(buddy);
+			if !page.is_head() || page.is_taken() || page.order() != order {
+				break;
+			}
+			self.remove(buddy, order);
+			idx = idx.min(buddy);
+			order += 1;
+		}
+		self.push(idx, order);
+	}
+}
+
+/// Allocate a page or multiple pages
+/// pages: the number of PAGE_SIZE pages to allocate, which is rounded up
+/// to a power of two, at most 2^MAX_ORDER
+pub fn alloc(pages: usize) -> *mut u8 {
+	assert!(pages > 0);
+	let order = order_for(pages);
+	if order > MAX_ORDER {
+		return null_mut();
+	}
+	let mut lists = FREE_LISTS.lock_irqsave();
+	unsafe {
+		// The smallest free block that is big enough
+		let Some(mut have) = (order..=MAX_ORDER).find(|&o| !lists.0[o].is_null())
+		else {
+			// If we get here, that means that no block was
+			// big enough.
+			return null_mut();
+		};
+		let block = lists.0[have];
+		let idx = (addr::virt_to_phys(block as usize) - ALLOC_START) / PAGE_SIZE;
+		lists.remove(idx, have);
+		// Split it, giving back the upper half each time, until it
+		// is just big enough.
+		while have > order {
+			have -= 1;
+			lists.push(idx + (1 << have), have);
+		}
+		let page = &mut *PAGES.add(idx);
+		page.flags = PageBits::Taken.val() | PageBits::Head.val();
+		page.order = order as u8;
+		// The Page structures themselves aren't the useful memory.
+		// Instead, there is 1 Page structure per 4096 bytes starting
+		// at ALLOC_START.
+		addr::phys_to_virt(ALLOC_START + PAGE_SIZE * idx) as *mut u8
+	}
+}
+
+/// Allocate and zero a page or multiple pages
+/// pages: the number of pages to allocate
+/// Each page is PAGE_SIZE which is calculated as 1 << PAGE_ORDER
+/// On RISC-V, this typically will be 4,096 bytes.
+pub fn zalloc(pages: usize) -> *mut u8 {
+	// Allocate and zero a page.
+	// First, let's get the allocation
+	let ret = alloc(pages);
+	if !ret.is_null() {
+		let size = (PAGE_SIZE * pages) / 8;
+		let big_ptr = ret as *mut u64;
+		for i in 0..size {
+			// We use big_ptr so that we can force an
+			// sd (store doubleword) instruction rather than
+			// the sb. This means 8x fewer stores than before.
+			// Typically we have to be concerned about remaining
+			// bytes, but fortunately 4096 % 8 = 0, so we
+			// won't have any remaining bytes.
+			unsafe {
+				(*big_ptr.add(i)) = 0;
+			}
+		}
+	}
+	ret
+}
+
+/// Deallocate a page by its pointer
+/// The block goes back to the free lists, merged with its buddies
+/// where they are free.
+pub fn dealloc(ptr: *mut u8) {
+	// Make sure we don't try to free a null pointer.
+	assert!(!ptr.is_null());
+	let mut lists = FREE_LISTS.lock_irqsave();
+	unsafe {
+		// Make sure that the address makes sense before we go looking
+		// for its Page structure.
+		let idx = addr::virt_to_phys(ptr as usize).wrapping_sub(ALLOC_START) / PAGE_SIZE;
+		assert!(idx < NUM_PAGES, "{:p} is not an allocated page", ptr);
+		let p = &*PAGES.add(idx);
+		assert!(!p.is_reserved(), "{:p} is not an allocated page", ptr);
+		// If the following assertion fails, it is most likely
+		// caused by a double-free, or a pointer into the middle of
+		// an allocation.
+		assert!(
+		        p.is_head() && p.is_taken(),
+		        "Possible double-free detected! ({:p} is not the start \
+		         of an allocation)",
+		        ptr
+		);
+		lists.free_block(idx, p.order());
+	}
+}
+
+/// Grow the allocation at `ptr` in place to `pages` pages, rounded up to
+/// a power of two, by taking in the free blocks that follow it. Returns
+/// false, and changes nothing, if they aren't all free.
+pub fn grow(ptr: *mut u8, pages: usize) -> bool {
+	let order = order_for(pages);
+	let mut lists = FREE_LISTS.lock_irqsave();
+	let have = allocation(ptr).expect("not an allocated block").order();
+	if order <= have {
+		return true;
+	}
+	if order > MAX_ORDER {
+		return false;
+	}
+	unsafe {
+		let idx = (addr::virt_to_phys(ptr as usize) - ALLOC_START) / PAGE_SIZE;
+		let pfn = (ALLOC_START >> PAGE_ORDER) + idx;
+		// At every step up, the block has to be the lower half and
+		// its buddy a free block of the same order.
+		let buddy_free = |o: usize| {
+			let Some(buddy) = buddy_of(idx, o)
+			else {
+				return false;
+			};
+			let page = &*PAGES.add(buddy);
+			pfn & (1 << o) == 0 && page.is_head() && !page.is_taken() && page.order() == o
+		};
+		if !(have..order).all(buddy_free) {
+			return false;
+		}
+		for o in have..order {
+			lists.remove(idx + (1 << o), o);
+		}
+		(*PAGES.add(idx)).order = order as u8;
+	}
+	true
+}
+
+// The Page structure of the block `ptr` points at, if it is the start
+// of an allocation. Only to be used with FREE_LISTS locked.
+fn allocation(ptr: *const u8) -> Option<&'static mut Page> {
+	unsafe {
+		let idx = addr::virt_to_phys(ptr as usize).wrapping_sub(ALLOC_START) / PAGE_SIZE;
+		if ptr as usize % PAGE_SIZE != 0 || idx >= NUM_PAGES {
+			return None;
+		}
+		let page = &mut *PAGES.add(idx);
+		(page.is_head() && page.is_taken() && !page.is_reserved()).then_some(page)
+	}
+}
+
+/// Mark the block at `ptr`, as returned by alloc(), as a slab. The mark
+/// goes away when the block is freed.
+pub fn set_slab(ptr: *mut u8) {
+	let _lists = FREE_LISTS.lock_irqsave();
+	let page = allocation(ptr).expect("not an allocated block");
+	page.set_flag(PageBits::Slab);
+}
+
+/// Is `ptr` the start of a block marked with set_slab()?
+pub fn is_slab(ptr: *const u8) -> bool {
+	let _lists = FREE_LISTS.lock_irqsave();
+	allocation(ptr).map_or(false, |page| page.is_slab())
+}
+
+/// How many pages the allocation at `ptr` really has, i.e. what was
+/// asked for rounded up to a power of two
+pub fn allocation_pages(ptr: *const u8) -> usize {
+	let _lists = FREE_LISTS.lock_irqsave();
+	let page = allocation(ptr).expect("not an allocated block");
+	1 << page.order()
+}
+
+/// Print all page allocations
+/// This is mainly used for debugging.
+pub fn print_page_allocations() {
+	let lists = FREE_LISTS.lock_irqsave();
+	unsafe {
+		let num_pages = NUM_PAGES;
+		let beg = PAGES as *const Page;
+		let end = beg.add(num_pages);
+		let alloc_beg = ALLOC_START;
+		let alloc_end = ALLOC_START + num_pages * PAGE_SIZE;
+		println!();
+		println!(
+		         "PAGE ALLOCATION TABLE\nMETA: {:p} -> {:p}\nPHYS: \
+		          0x{:x} -> 0x{:x}",
+		         beg, end, alloc_beg, alloc_end
+		);
+		println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+		let mut num = 0;
+		let mut reserved = 0;
+		let mut idx = 0;
+		while idx < num_pages {
+			let page = &*PAGES.add(idx);
+			if page.is_reserved() {
+				reserved += 1;
+				idx += 1;
+				continue;
+			}
+			// Pages that aren't reserved all belong to a block,
+			// which starts with its head.
+			let pages = 1 << page.order();
+			if page.is_taken() {
+				let memaddr = ALLOC_START + idx * PAGE_SIZE;
+				print!(
+				       "0x{:x} => 0x{:x}: {:>3} page(s)",
+				       memaddr,
+				       memaddr + pages * PAGE_SIZE - 1,
+				       pages
+				);
+				println!(".");
+				num += pages;
+			}
+			idx += pages;
+		}
+		println!("~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~");
+		println!(
+		         "Allocated: {:>6} pages ({:>10} bytes).",
+		         num,
+		         num * PAGE_SIZE
+		);
+		println!(
+		         "Free     : {:>6} pages ({:>10} bytes).",
+		         num_pages - num - reserved,
+		         (num_pages - num - reserved) * PAGE_SIZE
+		);
+		print!("Free blocks by order:");
+		for &head in lists.0.iter() {
+			let mut count = 0;
+			let mut block = head;
+			while !block.is_null() {
+				count += 1;
+				block = (*block).next;
+			}
+			print!(" {}", count);
+		}
+		println!();
+		println!();
+	}
+}
+
+// ////////////////////////////////
+// // MMU Routines
+// ////////////////////////////////
+
+// Represent (repr) our entry bits as
+// unsigned 64-bit integers.
+#[repr(i64)]
+#[derive(Copy, Clone)]
+pub enum EntryBits {
+	None = 0,
+	Valid = 1 << 0,
+	Read = 1 << 1,
+	Write = 1 << 2,
+	Execute = 1 << 3,
+	User = 1 << 4,
+	Global = 1 << 5,
+	Access = 1 << 6,
+	Dirty = 1 << 7,
+
+	// Convenience combinations
+	ReadWrite = 1 << 1 | 1 << 2,
+	ReadExecute = 1 << 1 | 1 << 3,
+	ReadWriteExecute = 1 << 1 | 1 << 2 | 1 << 3,
+
+	// User Convenience Combinations
+	UserReadWrite = 1 << 1 | 1 << 2 | 1 << 4,
+	UserReadExecute = 1 << 1 | 1 << 3 | 1 << 4,
+	UserReadWriteExecute = 1 << 1 | 1 << 2 | 1 << 3 | 1 << 4,
+}
+
+// Helper functions to convert the enumeration
+// into an i64, which is what our page table
+// entries will be.
+impl EntryBits {
+	pub fn val(self) -> i64 {
+		self as i64
+	}
+}
+
+// A single entry. We're using an i64 so that
+// this will sign-extend rather than zero-extend
+// since RISC-V requires that the reserved sections
+// take on the most significant bit.
+pub struct Entry {
+	pub entry: i64,
+}
+
+// The Entry structure describes one of the 512 entries per table, which is
+// described in the RISC-V privileged spec Figure 4.18.
+impl Entry {
+	pub fn is_valid(&self) -> bool {
+		self.get_entry() & EntryBits::Valid.val() != 0
+	}
+
+	// The first bit (bit index #0) is the V bit for
+	// valid.
+	pub fn is_invalid(&self) -> bool {
+		!self.is_valid()
+	}
+
+	// A leaf has one or more RWX bits set
+	pub fn is_leaf(&self) -> bool {
+		self.get_entry() & 0xe != 0
+	}
+
+	pub fn is_branch(&self) -> bool {
+		!self.is_leaf()
+	}
+
+	pub fn is_readable(&self) -> bool {
+		self.get_entry() & EntryBits::Read.val() != 0
+	}
+
+	pub fn is_writable(&self) -> bool {
+		self.get_entry() & EntryBits::Write.val() != 0
+	}
+
+	pub fn is_executable(&self) -> bool {
+		self.get_entry() & EntryBits::Execute.val() != 0
+	}
+
+	pub fn is_user(&self) -> bool {
+		self.get_entry() & EntryBits::User.val() != 0
+	}
+
+	pub fn is_global(&self) -> bool {
+		self.get_entry() & EntryBits::Global.val() != 0
+	}
+
+	pub fn is_accessed(&self) -> bool {
+		self.get_entry() & EntryBits::Access.val() != 0
+	}
+
+	pub fn is_dirty(&self) -> bool {
+		self.get_entry() & EntryBits::Dirty.val() != 0
+	}
+
+	// The physical address held in the PPN, i.e. the next table
+	// for a branch or the start of the page for a leaf.
+	pub fn addr(&self) -> PhysAddr {
+		PhysAddr::new(((self.get_entry() & !0x3ff) << 2) as usize)
+	}
+
+	pub fn set_entry(&mut self, entry: i64) {
+		self.entry = entry;
+	}
+
+	pub fn get_entry(&self) -> i64 {
+		self.entry
+	}
+}
+
+// Prints the V/R/W/X/U/G/A/D bits, with a '-' for each one that is clear.
+impl core::fmt::Display for Entry {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		for (i, c) in "VRWXUGAD".chars().enumerate() {
+			let set = self.get_entry() & (1 << i) != 0;
+			write!(f, "{}", if set { c } else { '-' })?;
+		}
+		Ok(())
+	}
+}
+
+// Table represents a single table, which contains 512 (2^9), 64-bit entries.
+pub struct Table {
+	pub entries: [Entry; 512],
+}
+
+impl Table {
+	pub fn len() -> usize {
+		512
+	}
+}
+
+/// Map a virtual address to a physical address using 4096-byte page
+/// size.
+/// root: a mutable reference to the root Table
+/// vaddr: The virtual address to map
+/// paddr: The physical address to map
+/// bits: An OR'd bitset containing the bits the leaf should have.
+///       The bits should contain only the following:
+///          Read, Write, Execute, User, and/or Global
+///       The bits MUST include one or more of the following:
+///          Read, Write, Execute
+///       The valid bit automatically gets added.
+/// Returns false, without mapping anything, if there was no page for a
+/// table on the way. Tables already added stay, empty.
+#[must_use]
+pub fn map(root: &mut Table,
+           vaddr: VirtAddr,
+           paddr: PhysAddr,
+           bits: i64,
+           level: usize)
+           -> bool
+{
+	// Make sure that Read, Write, or Execute have been provided
+	// otherwise, we'll leak memory and always create a page fault.
+	assert!(bits & 0xe != 0);
+	// We will use this as a floating reference so that we can set
+	// individual entries as we walk the table.
+	let top = mode::current().root_level();
+	let mut v = &mut root.entries[vaddr.vpn(top)];
+	// Now, we're going to traverse the page table and set the bits
+	// properly. We expect the root to be valid, however we're required to
+	// create anything beyond the root.
+	// In Rust, we create a range iterator using the .. operator.
+	// The .rev() will reverse the iteration since we need to start with
+	// the root's VPN. The .. operator is inclusive on start but exclusive
+	// on end. So, in Sv39, (0..2) will iterate 0 and 1.
+	for i in (level..top).rev() {
+		if !v.is_valid() {
+			// Allocate a page
+			let page = zalloc(1);
+			if page.is_null() {
+				return false;
+			}
+			// The page is already aligned by 4,096, so store its
+			// physical address directly The page is stored in the
+			// entry shifted right by 2 places.
+			v.set_entry(
+			            (addr::virt_to_phys(page as usize) as i64 >> 2)
+			            | EntryBits::Valid.val(),
+			);
+		}
+		let entry = v.addr().to_virt().as_ptr::<Entry>();
+		v = unsafe { entry.add(vaddr.vpn(i)).as_mut().unwrap() };
+	}
+	// When we get here, we should be at VPN[level] and v should be
+	// pointing to our entry.
+	// The entry structure is Figure 4.18 in the RISC-V Privileged
+	// Specification. Whatever the mode, the PPN is the 44 bits at
+	// [53:10].
+	let entry = ((paddr.as_usize() >> 12) << 10) as i64 | // PPN = [53:10]
+				bits |                    // Specified bits, such as User, Read, Write, etc
+				EntryBits::Valid.val() |  // Valid bit
+				EntryBits::Dirty.val() |  // Some machines require this to =1
+				EntryBits::Access.val()   // Just like dirty, some machines require this
+				;
+	// Set the entry. V should be set to the correct pointer by the loop
+	// above.
+	v.set_entry(entry);
+	true
+}
+
+/// Remove the 4 KiB mapping of `vaddr` from `root` and return the page
+/// it pointed at, or None if there was none. The tables on the way are
+/// left in place, and flushing the TLB is up to the caller.
+pub fn unmap_page(root: &mut Table, vaddr: VirtAddr) -> Option<PhysAddr> {
+	let top = mode::current().root_level();
+	let mut v = &mut root.entries[vaddr.vpn(top)];
+	for i in (0..top).rev() {
+		if !v.is_valid() || !v.is_branch() {
+			return None;
+		}
+		let entry = v.addr().to_virt().as_ptr::<Entry>();
+		v = unsafe { entry.add(vaddr.vpn(i)).as_mut().unwrap() };
+	}
+	if !v.is_valid() || !v.is_leaf() {
+		return None;
+	}
+	let paddr = v.addr();
+	v.set_entry(0);
+	Some(paddr)
+}
+
+/// Unmaps and frees all memory associated with a table.
+/// root: The root table to start freeing.
+/// NOTE: This does NOT free root directly. This must be
+/// freed manually.
+/// The reason we don't free the root is because it is
+/// usually embedded into the Process structure.
+pub fn unmap(root: &mut Table) {
+	unmap_level(root, mode::current().root_level());
+}
+
+// Free the tables under `table`, which is at `level`. Level 0 tables
+// cannot have branches, so they are freed without looking inside.
+fn unmap_level(table: &mut Table, level: usize) {
+	for entry in table.entries.iter() {
+		if entry.is_valid() && entry.is_branch() {
+			// This is a valid entry, so drill down and free.
+			let memaddr = entry.addr().to_virt();
+			if level > 1 {
+				let next = unsafe { memaddr.as_ptr::<Table>().as_mut().unwrap() };
+				unmap_level(next, level - 1);
+			}
+			dealloc(memaddr.as_ptr());
+		}
+	}
+}
+
+/// Walk the page table to convert a virtual address to a
+/// physical address.
+/// If a page fault would occur, this returns None
+/// Otherwise, it returns Some with the physical address.
+pub fn translate(root: &Table, vaddr: VirtAddr) -> Option<PhysAddr> {
+	// Walk the page table pointed to by root
+	let top = mode::current().root_level();
+	let mut v = &root.entries[vaddr.vpn(top)];
+	for i in (0..=top).rev() {
+		if v.is_invalid() {
+			// This is an invalid entry, page fault.
+			break;
+		}
+		else if v.is_leaf() {
+			// According to RISC-V, a leaf can be at any level.
+
+			// The offset mask masks off the PPN. Each PPN is 9
+			// bits and they start at bit #12. So, our formula
+			// 12 + i * 9
+			let off_mask = (1 << (12 + i * 9)) - 1;
+			let vaddr_pgoff = vaddr.as_usize() & off_mask;
+			let addr = ((v.get_entry() << 2) as usize) & !off_mask;
+			return Some(PhysAddr::new(addr | vaddr_pgoff));
+		}
+		// Set v to the next entry which is pointed to by this
+		// entry. However, the address was shifted right by 2 places
+		// when stored in the page table entry, so we shift it left
+		// to get it back into place.
+		let entry: *const Entry = v.addr().to_virt().as_ptr();
+		// We do i - 1 here, however we should get None or Some() above
+		// before we do 0 - 1 = -1.
+		v = unsafe { entry.add(vaddr.vpn(i - 1)).as_ref().unwrap() };
+	}
+
+	// If we get here, we've exhausted all valid tables and haven't
+	// found a leaf.
+	None
+}
@@ -0,0 +1,279 @@
+//! Which physical memory is ours to hand out
+//!
+//! The device tree says where RAM is. Not all of it is free: the kernel
+//! image, the device tree blob itself, an initrd and whatever the
+//! firmware reserved for itself have to be left alone. [`PhysMemoryMap`]
+//! starts from the RAM ranges and has those punched out, which leaves
+//! the ranges the page allocator may use.
+//!
+//! This runs before there is any allocator, so the map is a fixed size
+//! array of sorted, non-overlapping ranges.
+
+use core::fmt;
+
+use super::{layout::KernelLayout, page::PAGE_SIZE};
+use crate::{arch::boot, cmdline::Size, fdt::Fdt, kernel_param, println};
+
+kernel_param! {
+    /// Use no more than this much RAM, reserved parts included (`mem=`)
+    static MEM: Option<Size> = "mem", None;
+}
+
+/// Most ranges a map can hold. Real machines have a handful.
+pub const MAX_RANGES: usize = 32;
+
+/// The half open range of physical addresses `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PhysRange {
+    pub const EMPTY: Self = Self { start: 0, end: 0 };
+
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+
+    pub const fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub const fn overlaps(&self, other: &PhysRange) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// The whole pages inside this range
+    pub const fn page_aligned(&self) -> Self {
+        Self {
+            start: (self.start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            end: self.end & !(PAGE_SIZE - 1),
+        }
+    }
+}
+
+impl fmt::Display for PhysRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x} -> {:#010x}", self.start, self.end)
+    }
+}
+
+#[derive(Clone)]
+pub struct PhysMemoryMap {
+    ranges: [PhysRange; MAX_RANGES],
+    len: usize,
+}
+
+impl PhysMemoryMap {
+    pub const fn new() -> Self {
+        Self {
+            ranges: [PhysRange::EMPTY; MAX_RANGES],
+            len: 0,
+        }
+    }
+
+    /// Build the map from the device tree, or with no (usable) device
+    /// tree, from the RAM the linker script assumes.
+    pub fn from_firmware(fdt: Option<Fdt>) -> Self {
+        let mut map = Self::new();
+        let limit = MEM.get().map_or(usize::MAX, |Size(size)| size);
+        let Some(fdt) = fdt else {
+            map.add(KernelLayout::get().heap.phys());
+            map.truncate(limit);
+            return map.page_aligned();
+        };
+
+        for mem in fdt.memory() {
+            let start = mem.address as usize;
+            map.add(PhysRange::new(
+                start,
+                start + mem.size.unwrap_or(0) as usize,
+            ));
+        }
+        map.truncate(limit);
+
+        map.remove(kernel_image());
+        if let Some(dtb) = boot::dtb() {
+            map.remove(PhysRange::new(dtb, dtb + fdt.total_size()));
+        }
+        if let Some(initrd) = initrd(&fdt) {
+            map.remove(initrd);
+        }
+        for reg in fdt.reserved_memory().chain(fdt.memory_reservations()) {
+            let start = reg.address as usize;
+            map.remove(PhysRange::new(
+                start,
+                start + reg.size.unwrap_or(0) as usize,
+            ));
+        }
+        map.page_aligned()
+    }
+
+    pub fn ranges(&self) -> &[PhysRange] {
+        &self.ranges[..self.len]
+    }
+
+    /// Bytes covered by the map
+    pub fn total(&self) -> usize {
+        self.ranges().iter().map(PhysRange::len).sum()
+    }
+
+    /// From the start of the first range to the end of the last
+    pub fn span(&self) -> Option<PhysRange> {
+        let (first, last) = (self.ranges().first()?, self.ranges().last()?);
+        Some(PhysRange::new(first.start, last.end))
+    }
+
+    pub fn largest(&self) -> Option<PhysRange> {
+        self.ranges().iter().copied().max_by_key(PhysRange::len)
+    }
+
+    fn insert(&mut self, at: usize, range: PhysRange) {
+        if self.len == MAX_RANGES {
+            println!("Memory map full, dropping {}", range);
+            return;
+        }
+        self.ranges.copy_within(at..self.len, at + 1);
+        self.ranges[at] = range;
+        self.len += 1;
+    }
+
+    fn delete(&mut self, at: usize) {
+        self.ranges.copy_within(at + 1..self.len, at);
+        self.len -= 1;
+    }
+
+    /// Add `range`, merging it with any range it overlaps or touches
+    pub fn add(&mut self, range: PhysRange) {
+        if range.is_empty() {
+            return;
+        }
+        let mut merged = range;
+        let mut i = 0;
+        while i < self.len {
+            let r = self.ranges[i];
+            if r.end < merged.start || merged.end < r.start {
+                i += 1;
+                continue;
+            }
+            merged = PhysRange::new(r.start.min(merged.start), r.end.max(merged.end));
+            self.delete(i);
+        }
+        let at = self.ranges().partition_point(|r| r.start < merged.start);
+        self.insert(at, merged);
+    }
+
+    /// Take `range` out of the map, splitting ranges it is in the middle of
+    pub fn remove(&mut self, range: PhysRange) {
+        if range.is_empty() {
+            return;
+        }
+        let mut i = 0;
+        while i < self.len {
+            let r = self.ranges[i];
+            if r.end <= range.start || range.end <= r.start {
+                i += 1;
+                continue;
+            }
+            let below = PhysRange::new(r.start, range.start);
+            let above = PhysRange::new(range.end, r.end);
+            self.delete(i);
+            if !above.is_empty() {
+                self.insert(i, above);
+            }
+            if !below.is_empty() {
+                self.insert(i, below);
+                i += 1;
+            }
+        }
+    }
+
+    /// Keep only the lowest `size` bytes of the map
+    pub fn truncate(&mut self, size: usize) {
+        let mut left = size;
+        for i in 0..self.len {
+            if left == 0 {
+                self.len = i;
+                return;
+            }
+            let keep = self.ranges[i].len().min(left);
+            self.ranges[i].end = self.ranges[i].start + keep;
+            left -= keep;
+        }
+    }
+
+    /// Carve `size` bytes aligned to `align` out of the first range with
+    /// room for them.
+    pub fn take(&mut self, size: usize, align: usize) -> Option<usize> {
+        let start = self.ranges().iter().find_map(|r| {
+            let start = (r.start + align - 1) & !(align - 1);
+            (start + size <= r.end).then_some(start)
+        })?;
+        self.remove(PhysRange::new(start, start + size));
+        Some(start)
+    }
+
+    /// Only whole pages are any use to the page allocator
+    fn page_aligned(mut self) -> Self {
+        let mut i = 0;
+        while i < self.len {
+            self.ranges[i] = self.ranges[i].page_aligned();
+            if self.ranges[i].is_empty() {
+                self.delete(i);
+            } else {
+                i += 1;
+            }
+        }
+        self
+    }
+}
+
+impl Default for PhysMemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for PhysMemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in self.ranges() {
+            writeln!(f, "  {} ({} KiB)", r, r.len() / 1024)?;
+        }
+        write!(f, "  {} MiB usable", self.total() / (1024 * 1024))
+    }
+}
+
+/// Where the kernel image and its boot stacks are in physical memory
+pub fn kernel_image() -> PhysRange {
+    KernelLayout::get().image().phys()
+}
+
+/// Where the bootloader put the initrd, according to `/chosen`
+fn initrd(fdt: &Fdt) -> Option<PhysRange> {
+    let chosen = fdt.chosen()?;
+    let start = chosen.property("linux,initrd-start")?.as_usize()?;
+    let end = chosen.property("linux,initrd-end")?.as_usize()?;
+    Some(PhysRange::new(start, end))
+}
+
+/// What was free once the page allocator's own bookkeeping was taken
+/// out, see [`memory_map`]
+static mut MEMORY_MAP: PhysMemoryMap = PhysMemoryMap::new();
+
+pub(super) fn set_memory_map(map: PhysMemoryMap) {
+    unsafe { MEMORY_MAP = map };
+}
+
+/// The memory the page allocator was given
+pub fn memory_map() -> &'static PhysMemoryMap {
+    unsafe { &*core::ptr::addr_of!(MEMORY_MAP) }
+}
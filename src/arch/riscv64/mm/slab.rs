@@ -0,0 +1,275 @@
+//! Slab caches for small kernel objects
+//!
+//! A [`Cache`] hands out objects of a single size. It takes memory from
+//! the page allocator a slab at a time: a [`SLAB_SIZE`] block with a
+//! [`Slab`] header at the start and the rest cut into objects, the free
+//! ones linked through their first word. Allocating and freeing is a list
+//! push or pop, with no walking.
+//!
+//! Slabs come from the buddy allocator, so they are aligned to their
+//! size, and the slab an object lives in is found by rounding its address
+//! down. The page allocator marks slab blocks, which is how [`owns`]
+//! tells a slab object from a block of whole pages.
+//!
+//! [`kmem`](super::kmem) keeps a cache per size class for `kmalloc`.
+//! Fixed-size kernel objects can have a named cache of their own:
+//!
+//! ```ignore
+//! static TASKS: Cache = Cache::new("task", size_of::<Task>(), align_of::<Task>());
+//! ```
+
+use core::{mem::size_of, ptr::null_mut};
+
+use super::page::{self, PAGE_SIZE};
+use crate::{println, sync::spinlock::SpinLock};
+
+/// Slabs are blocks of `1 << SLAB_ORDER` pages
+pub const SLAB_ORDER: usize = 2;
+pub const SLAB_SIZE: usize = PAGE_SIZE << SLAB_ORDER;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// The header at the start of every slab
+struct Slab {
+    cache: *const Cache,
+    free: *mut FreeObject,
+    in_use: usize,
+    next: *mut Slab,
+    prev: *mut Slab,
+}
+
+/// A cache's slabs by how full they are
+struct Slabs {
+    partial: *mut Slab,
+    full: *mut Slab,
+    /// One slab with nothing in use is kept back, so that allocating and
+    /// freeing right at a slab boundary doesn't hit the page allocator
+    /// every time
+    empty: *mut Slab,
+    count: usize,
+    in_use: usize,
+}
+
+// The slabs are only reached through the cache's lock
+unsafe impl Send for Slabs {}
+
+/// A cache of same-sized objects
+pub struct Cache {
+    name: &'static str,
+    size: usize,
+    /// Where the first object starts in a slab
+    offset: usize,
+    slabs: SpinLock<Slabs>,
+}
+
+impl Cache {
+    /// A cache of `size` byte objects aligned to `align`, which must be a
+    /// power of two. It is `const` so that caches can be statics.
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        assert!(align.is_power_of_two());
+        let size = align_up(max(size, size_of::<FreeObject>()), align);
+        let offset = align_up(size_of::<Slab>(), align);
+        assert!(offset + size <= SLAB_SIZE, "object too big for a slab");
+        Self {
+            name,
+            size,
+            offset,
+            slabs: SpinLock::new(Slabs {
+                partial: null_mut(),
+                full: null_mut(),
+                empty: null_mut(),
+                count: 0,
+                in_use: 0,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The size of the objects, after rounding up for alignment
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn objects_per_slab(&self) -> usize {
+        (SLAB_SIZE - self.offset) / self.size
+    }
+
+    /// Allocate an object, or return null if the page allocator is out of
+    /// memory. The object is not zeroed.
+    pub fn alloc(&'static self) -> *mut u8 {
+        let mut slabs = self.slabs.lock_irqsave();
+        let slab = if !slabs.partial.is_null() {
+            slabs.partial
+        } else {
+            let slab = if !slabs.empty.is_null() {
+                core::mem::replace(&mut slabs.empty, null_mut())
+            } else {
+                let slab = self.grow();
+                if slab.is_null() {
+                    return null_mut();
+                }
+                slabs.count += 1;
+                slab
+            };
+            push(&mut slabs.partial, slab);
+            slab
+        };
+        slabs.in_use += 1;
+        unsafe {
+            let object = (*slab).free;
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            if (*slab).free.is_null() {
+                remove(&mut slabs.partial, slab);
+                push(&mut slabs.full, slab);
+            }
+            object as *mut u8
+        }
+    }
+
+    /// Allocate a zeroed object
+    pub fn zalloc(&'static self) -> *mut u8 {
+        let object = self.alloc();
+        if !object.is_null() {
+            unsafe { object.write_bytes(0, self.size) };
+        }
+        object
+    }
+
+    /// Give back an object that came from this cache
+    pub fn free(&self, object: *mut u8) {
+        let slab = slab_of(object);
+        unsafe {
+            assert!(
+                (*slab).cache == self as *const Cache,
+                "{:p} is not from the {} cache",
+                object,
+                self.name
+            );
+            let index = (object as usize - slab as usize)
+                .checked_sub(self.offset)
+                .map(|off| off % self.size);
+            assert!(index == Some(0), "{:p} is not an object", object);
+        }
+        let mut slabs = self.slabs.lock_irqsave();
+        slabs.in_use -= 1;
+        unsafe {
+            let object = object as *mut FreeObject;
+            if (*slab).free.is_null() {
+                remove(&mut slabs.full, slab);
+                push(&mut slabs.partial, slab);
+            }
+            (*object).next = (*slab).free;
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+            if (*slab).in_use == 0 {
+                remove(&mut slabs.partial, slab);
+                if slabs.empty.is_null() {
+                    slabs.empty = slab;
+                } else {
+                    slabs.count -= 1;
+                    page::dealloc(slab as *mut u8);
+                }
+            }
+        }
+    }
+
+    /// Get a new slab from the page allocator and thread its objects onto
+    /// the free list, in address order
+    fn grow(&self) -> *mut Slab {
+        let block = page::alloc(1 << SLAB_ORDER);
+        if block.is_null() {
+            return null_mut();
+        }
+        page::set_slab(block);
+        let slab = block as *mut Slab;
+        unsafe {
+            let first = block.add(self.offset);
+            let count = self.objects_per_slab();
+            for i in 0..count {
+                let object = first.add(i * self.size) as *mut FreeObject;
+                (*object).next = if i + 1 < count {
+                    first.add((i + 1) * self.size) as *mut FreeObject
+                } else {
+                    null_mut()
+                };
+            }
+            slab.write(Slab {
+                cache: self,
+                free: first as *mut FreeObject,
+                in_use: 0,
+                next: null_mut(),
+                prev: null_mut(),
+            });
+        }
+        slab
+    }
+
+    pub fn print(&self) {
+        let slabs = self.slabs.lock_irqsave();
+        println!(
+            "{:<14}{:>6} B  {:>6} in use  {:>4} slabs",
+            self.name, self.size, slabs.in_use, slabs.count
+        );
+    }
+}
+
+/// Does `ptr` point into a slab?
+pub fn owns(ptr: *const u8) -> bool {
+    page::is_slab(slab_of(ptr) as *const u8)
+}
+
+/// The cache the slab object at `ptr` came from
+pub fn cache_of(ptr: *const u8) -> &'static Cache {
+    unsafe { &*(*slab_of(ptr)).cache }
+}
+
+/// Free a slab object without knowing its cache
+pub fn free(ptr: *mut u8) {
+    cache_of(ptr).free(ptr);
+}
+
+fn slab_of(ptr: *const u8) -> *mut Slab {
+    (ptr as usize & !(SLAB_SIZE - 1)) as *mut Slab
+}
+
+fn push(list: &mut *mut Slab, slab: *mut Slab) {
+    unsafe {
+        (*slab).prev = null_mut();
+        (*slab).next = *list;
+        if !list.is_null() {
+            (**list).prev = slab;
+        }
+    }
+    *list = slab;
+}
+
+fn remove(list: &mut *mut Slab, slab: *mut Slab) {
+    unsafe {
+        if (*slab).prev.is_null() {
+            *list = (*slab).next;
+        } else {
+            (*(*slab).prev).next = (*slab).next;
+        }
+        if !(*slab).next.is_null() {
+            (*(*slab).next).prev = (*slab).prev;
+        }
+    }
+}
+
+const fn align_up(val: usize, align: usize) -> usize {
+    (val + align - 1) & !(align - 1)
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
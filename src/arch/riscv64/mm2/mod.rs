@@ -1,126 +0,0 @@
-use crate::println;
-
-pub mod kmem;
-pub mod page;
-
-extern "C" {
-    static TEXT_START: usize;
-    static TEXT_END: usize;
-    static DATA_START: usize;
-    static DATA_END: usize;
-    static RODATA_START: usize;
-    static RODATA_END: usize;
-    static BSS_START: usize;
-    static BSS_END: usize;
-    static KERNEL_STACK_START: usize;
-    static KERNEL_STACK_END: usize;
-    static HEAP_START: usize;
-    static HEAP_SIZE: usize;
-    static mut KERNEL_TABLE: usize;
-}
-
-pub fn init() {
-    page::init();
-
-    kmem::init();
-    let root_ptr = kmem::get_page_table();
-    let root_u = root_ptr as usize;
-    let mut root = unsafe { root_ptr.as_mut().unwrap() };
-    let kheap_head = kmem::get_head() as usize;
-    let total_pages = kmem::get_num_allocations();
-    println!();
-    println!();
-    unsafe {
-        println!("TEXT:   0x{:x} -> 0x{:x}", TEXT_START, TEXT_END);
-        println!("RODATA: 0x{:x} -> 0x{:x}", RODATA_START, RODATA_END);
-        println!("DATA:   0x{:x} -> 0x{:x}", DATA_START, DATA_END);
-        println!("BSS:    0x{:x} -> 0x{:x}", BSS_START, BSS_END);
-        println!(
-            "STACK:  0x{:x} -> 0x{:x}",
-            KERNEL_STACK_START, KERNEL_STACK_END
-        );
-        println!(
-            "HEAP:   0x{:x} -> 0x{:x}",
-            kheap_head,
-            kheap_head + total_pages * 4096
-        );
-    }
-    id_map_range(
-        &mut root,
-        kheap_head,
-        kheap_head + total_pages * 4096,
-        page::EntryBits::ReadWrite.val(),
-    );
-    unsafe {
-        // Map heap descriptors
-        let num_pages = HEAP_SIZE / page::PAGE_SIZE;
-        id_map_range(
-            &mut root,
-            HEAP_START,
-            HEAP_START + num_pages,
-            page::EntryBits::ReadWrite.val(),
-        );
-        // Map executable section
-        id_map_range(
-            &mut root,
-            TEXT_START,
-            TEXT_END,
-            page::EntryBits::ReadExecute.val(),
-        );
-        // Map rodata section
-        // We put the ROdata section into the text section, so they can
-        // potentially overlap however, we only care that it's read
-        // only.
-        id_map_range(
-            &mut root,
-            RODATA_START,
-            RODATA_END,
-            page::EntryBits::ReadExecute.val(),
-        );
-        // Map data section
-        id_map_range(
-            &mut root,
-            DATA_START,
-            DATA_END,
-            page::EntryBits::ReadWrite.val(),
-        );
-        // Map bss section
-        id_map_range(
-            &mut root,
-            BSS_START,
-            BSS_END,
-            page::EntryBits::ReadWrite.val(),
-        );
-        // Map kernel stack
-        id_map_range(
-            &mut root,
-            KERNEL_STACK_START,
-            KERNEL_STACK_END,
-            page::EntryBits::ReadWrite.val(),
-        );
-    }
-
-    // UART
-    page::map(
-        &mut root,
-        0x1000_0000,
-        0x1000_0000,
-        page::EntryBits::ReadWrite.val(),
-        0,
-    );
-    kmem::print_table();
-}
-
-pub fn id_map_range(root: &mut page::Table, start: usize, end: usize, bits: i64) {
-    let mut memaddr = start & !(page::PAGE_SIZE - 1);
-    let num_kb_pages = (page::align_val(end, 12) - memaddr) / page::PAGE_SIZE;
-
-    // I named this num_kb_pages for future expansion when
-    // I decide to allow for GiB (2^30) and 2MiB (2^21) page
-    // sizes. However, the overlapping memory regions are causing
-    // nightmares.
-    for _ in 0..num_kb_pages {
-        page::map(root, memaddr, memaddr, bits, 0);
-        memaddr += 1 << 12;
-    }
-}
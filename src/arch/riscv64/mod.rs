@@ -1,28 +1,120 @@
-use crate::println;
+use crate::{
+    cmdline,
+    drivers::{plic, serial},
+    fdt::Fdt,
+    println,
+};
 
+use trap::Interrupt;
+
+pub mod backtrace;
 pub mod boot;
+pub mod csr;
+pub mod ipi;
+pub mod irq;
 pub mod mm;
+pub mod percpu;
+pub mod sbi;
+pub mod smp;
+pub mod timer;
 pub mod trap;
-pub mod mm2;
+
+/// The most harts we'll ever bring up. The boot stack is carved
+/// into 64 KiB slices per hart, which gives us room for eight.
+pub const MAX_HARTS: usize = 8;
 
 struct RiscV64;
 
 impl super::Arch for RiscV64 {
     #[no_mangle]
-    extern "C" fn kinit() {
+    extern "C" fn kinit(hartid: usize, dtb: usize) {
+        smp::init_boot_hart(hartid);
+        boot::set_dtb(dtb);
         println!("Walnut initializing...");
-        mm2::init();
+        #[cfg(feature = "riscv-sbi")]
+        sbi_info();
+        describe_machine(dtb);
+        cmdline::init();
+        timer::probe();
+        plic::probe();
+        mm::init();
+        timer::init();
+        plic::init_hart(hartid);
+        serial::init_interrupts();
+        smp::boot_secondaries();
         unsafe { core::arch::asm!("nop;nop;") }
     }
 }
 
+/// Say what the device tree says we're running on, or that we're
+/// guessing.
+fn describe_machine(dtb: usize) {
+    let fdt = match unsafe { Fdt::from_ptr(mm::addr::phys_to_virt(dtb) as *const u8) } {
+        Ok(fdt) => fdt,
+        Err(e) => {
+            println!("No device tree at {:#x} ({}), assuming QEMU virt", dtb, e);
+            return;
+        }
+    };
+    println!("Device tree at {:#x}, {} bytes", dtb, fdt.total_size());
+    for cpu in fdt.cpus() {
+        println!(
+            "  hart {}: {}",
+            cpu.hartid().unwrap_or(usize::MAX),
+            cpu.isa().unwrap_or("unknown ISA")
+        );
+    }
+    for mem in fdt.memory() {
+        println!(
+            "  memory {:#x} + {:#x}",
+            mem.address,
+            mem.size.unwrap_or(0)
+        );
+    }
+}
+
+/// Say what firmware we're running on and complain about anything
+/// missing that we rely on.
+#[cfg(feature = "riscv-sbi")]
+fn sbi_info() {
+    let (major, minor) = sbi::spec_version();
+    println!(
+        "SBI v{}.{}, implementation {} version {:#x}",
+        major,
+        minor,
+        sbi::impl_id(),
+        sbi::impl_version()
+    );
+    for (eid, name) in [
+        (sbi::TIME_EID, "TIME"),
+        (sbi::IPI_EID, "IPI"),
+        (sbi::RFENCE_EID, "RFENCE"),
+        (sbi::HSM_EID, "HSM"),
+        (sbi::SRST_EID, "SRST"),
+    ] {
+        if !sbi::probe_extension(eid) {
+            println!("SBI firmware lacks the {} extension", name);
+        }
+    }
+}
+
 impl RiscV64 {
+    /// The first Rust code a secondary hart runs, once it is in
+    /// supervisor mode on its own stack. See `smp`.
     #[no_mangle]
-    extern "C" fn kinit_hart() {
-        let hart_id: usize;
+    extern "C" fn kinit_hart(hartid: usize) {
+        smp::init_hart(hartid);
+        timer::init();
+        plic::init_hart(hartid);
         unsafe {
-            core::arch::asm!("csrr {}, mhartid", out(reg) hart_id);
+            csr::sie::set(
+                Interrupt::SupervisorSoftware.mask()
+                    | Interrupt::SupervisorTimer.mask()
+                    | Interrupt::SupervisorExternal.mask(),
+            );
         }
-        println!("Hello from hart thread {} ", hart_id);
+        smp::mark_online(hartid);
+        println!("Hello from hart thread {} ", percpu::hartid());
+        unsafe { irq::enable() };
     }
 }
@@ -0,0 +1,127 @@
+//! Per-hart data
+//!
+//! Each hart has a [`PerCpu`] block, and while running kernel code `tp`
+//! points at the one belonging to the hart it is read on. Nothing in the
+//! kernel uses thread local storage, so `tp` is free for this. The block
+//! is also reachable from the trap frame `sscratch` points at, and
+//! `s_trap_vector` reloads `tp` from there before calling into Rust.
+//!
+//! Fields are only ever written by their own hart, but other harts may
+//! read them, so everything that changes is an atomic.
+
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use super::{trap::TrapFrame, MAX_HARTS};
+
+/// Things worth counting per hart
+pub struct Counters {
+    pub interrupts: AtomicU64,
+    pub exceptions: AtomicU64,
+    pub ticks: AtomicU64,
+    pub ipis: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            interrupts: AtomicU64::new(0),
+            exceptions: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            ipis: AtomicU64::new(0),
+        }
+    }
+}
+
+pub struct PerCpu {
+    pub hartid: usize,
+    /// The task running on this hart, or 0 if it is idle
+    pub current_task: AtomicUsize,
+    /// How many interrupt handlers deep we are
+    pub irq_depth: AtomicUsize,
+    /// `ipi::IpiKind` bits other harts have asked us to handle
+    pub ipi_pending: AtomicUsize,
+    /// This hart's frame in `KERNEL_TRAP_FRAMES`
+    pub trap_frame: *mut TrapFrame,
+    pub counters: Counters,
+}
+
+/// The raw pointers are only ever dereferenced by their own hart
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            hartid: 0,
+            current_task: AtomicUsize::new(0),
+            irq_depth: AtomicUsize::new(0),
+            ipi_pending: AtomicUsize::new(0),
+            trap_frame: core::ptr::null_mut(),
+            counters: Counters::new(),
+        }
+    }
+
+    /// Are we running in an interrupt handler?
+    pub fn in_interrupt(&self) -> bool {
+        self.irq_depth.load(Ordering::Relaxed) != 0
+    }
+}
+
+static mut PERCPU: [PerCpu; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: PerCpu = PerCpu::new();
+    [EMPTY; MAX_HARTS]
+};
+
+/// Set up the calling hart's block and point `tp` (and its trap frame)
+/// at it. Must run on each hart before anything uses [`current`].
+///
+/// # Safety
+///
+/// `hartid` must be the id of the calling hart and nothing may be using
+/// its block yet.
+pub unsafe fn init(hartid: usize, trap_frame: *mut TrapFrame) {
+    let cpu = addr_of_mut!(PERCPU[hartid]);
+    (*cpu).hartid = hartid;
+    (*cpu).trap_frame = trap_frame;
+    (*trap_frame).hartid = hartid;
+    (*trap_frame).percpu = cpu as usize;
+    core::arch::asm!("mv tp, {}", in(reg) cpu);
+}
+
+/// The calling hart's block
+#[inline]
+pub fn current() -> &'static PerCpu {
+    let tp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, tp", out(reg) tp);
+        &*(tp as *const PerCpu)
+    }
+}
+
+/// Another hart's block
+pub fn of(hartid: usize) -> &'static PerCpu {
+    unsafe { &*core::ptr::addr_of!(PERCPU[hartid]) }
+}
+
+/// The id of the calling hart. Cheaper than a trip to machine mode
+/// for `mhartid`, which supervisor mode can't read.
+#[inline]
+pub fn hartid() -> usize {
+    current().hartid
+}
+
+/// A field of the calling hart's [`PerCpu`] block.
+///
+/// ```ignore
+/// percpu!(counters).ticks.fetch_add(1, Ordering::Relaxed);
+/// let id = *percpu!(hartid);
+/// ```
+#[macro_export]
+macro_rules! percpu {
+    ($field:ident) => {
+        &$crate::arch::percpu::current().$field
+    };
+}
@@ -0,0 +1,270 @@
+//! Calls into the Supervisor Binary Interface
+//!
+//! Supervisor mode asks machine mode for the things it can't do itself
+//! with an `ecall`. `a7` holds the extension id, `a6` the function id and
+//! `a0`-`a5` the arguments. The result comes back in `a0` (error) and
+//! `a1` (value).
+//!
+//! Machine mode is either our own `trap::firmware`, or with the
+//! `riscv-sbi` feature, whatever firmware loaded us.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use super::mm::addr;
+
+/// The base extension, always present
+pub const BASE_EID: usize = 0x10;
+pub const BASE_GET_SPEC_VERSION: usize = 0;
+pub const BASE_GET_IMPL_ID: usize = 1;
+pub const BASE_GET_IMPL_VERSION: usize = 2;
+pub const BASE_PROBE_EXTENSION: usize = 3;
+
+/// `"TIME"`
+pub const TIME_EID: usize = 0x5449_4d45;
+pub const TIME_SET_TIMER: usize = 0;
+
+/// `"sPI"`
+pub const IPI_EID: usize = 0x0073_5049;
+pub const IPI_SEND_IPI: usize = 0;
+
+/// `"RFNC"`
+pub const RFENCE_EID: usize = 0x5246_4e43;
+pub const RFENCE_REMOTE_FENCE_I: usize = 0;
+pub const RFENCE_REMOTE_SFENCE_VMA: usize = 1;
+
+/// `"HSM"`
+pub const HSM_EID: usize = 0x0048_534d;
+pub const HSM_HART_START: usize = 0;
+pub const HSM_HART_STOP: usize = 1;
+pub const HSM_HART_GET_STATUS: usize = 2;
+
+/// `"SRST"`
+pub const SRST_EID: usize = 0x5352_5354;
+pub const SRST_SYSTEM_RESET: usize = 0;
+
+/// `"DBCN"`
+pub const DBCN_EID: usize = 0x4442_434e;
+pub const DBCN_CONSOLE_WRITE: usize = 0;
+pub const DBCN_CONSOLE_WRITE_BYTE: usize = 2;
+
+/// The v0.1 `console_putchar`, for firmware without `DBCN`
+pub const LEGACY_CONSOLE_PUTCHAR_EID: usize = 0x01;
+
+/// A `hart_mask_base` of this means "every hart", ignoring `hart_mask`
+pub const HART_MASK_BASE_ALL: usize = usize::MAX;
+
+pub const SBI_SUCCESS: isize = 0;
+pub const SBI_ERR_FAILED: isize = -1;
+pub const SBI_ERR_NOT_SUPPORTED: isize = -2;
+pub const SBI_ERR_INVALID_PARAM: isize = -3;
+pub const SBI_ERR_ALREADY_AVAILABLE: isize = -6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SbiRet {
+    pub error: isize,
+    pub value: usize,
+}
+
+impl SbiRet {
+    pub fn is_ok(&self) -> bool {
+        self.error == SBI_SUCCESS
+    }
+}
+
+#[inline]
+fn ecall(eid: usize, fid: usize, args: [usize; 5]) -> SbiRet {
+    let error: isize;
+    let value: usize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a6") fid,
+            in("a7") eid,
+        );
+    }
+    SbiRet { error, value }
+}
+
+/// Version of the SBI spec the firmware implements, as `(major, minor)`
+pub fn spec_version() -> (usize, usize) {
+    let v = ecall(BASE_EID, BASE_GET_SPEC_VERSION, [0; 5]).value;
+    ((v >> 24) & 0x7f, v & 0xff_ffff)
+}
+
+/// Which SBI implementation we're talking to (1 is OpenSBI)
+pub fn impl_id() -> usize {
+    ecall(BASE_EID, BASE_GET_IMPL_ID, [0; 5]).value
+}
+
+pub fn impl_version() -> usize {
+    ecall(BASE_EID, BASE_GET_IMPL_VERSION, [0; 5]).value
+}
+
+/// Does the firmware implement extension `eid`?
+pub fn probe_extension(eid: usize) -> bool {
+    ecall(BASE_EID, BASE_PROBE_EXTENSION, [eid, 0, 0, 0, 0]).value != 0
+}
+
+/// Program the next timer interrupt for this hart at `stime_value`
+/// (in units of the `time` CSR). This also clears any pending
+/// supervisor timer interrupt.
+pub fn set_timer(stime_value: u64) -> SbiRet {
+    ecall(TIME_EID, TIME_SET_TIMER, [stime_value as usize, 0, 0, 0, 0])
+}
+
+/// Raise a supervisor software interrupt on every hart `h` for which bit
+/// `h - hart_mask_base` of `hart_mask` is set.
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiRet {
+    ecall(IPI_EID, IPI_SEND_IPI, [hart_mask, hart_mask_base, 0, 0, 0])
+}
+
+/// `fence.i` on the harts in `hart_mask`
+pub fn remote_fence_i(hart_mask: usize, hart_mask_base: usize) -> SbiRet {
+    ecall(
+        RFENCE_EID,
+        RFENCE_REMOTE_FENCE_I,
+        [hart_mask, hart_mask_base, 0, 0, 0],
+    )
+}
+
+/// `sfence.vma` for `size` bytes from `start` on the harts in `hart_mask`.
+/// A `start` and `size` of 0 and `usize::MAX` flush everything.
+pub fn remote_sfence_vma(
+    hart_mask: usize,
+    hart_mask_base: usize,
+    start: usize,
+    size: usize,
+) -> SbiRet {
+    ecall(
+        RFENCE_EID,
+        RFENCE_REMOTE_SFENCE_VMA,
+        [hart_mask, hart_mask_base, start, size, 0],
+    )
+}
+
+/// Start the stopped hart `hartid` in supervisor mode at `start_addr`,
+/// with its hart id in `a0`, `opaque` in `a1` and paging off.
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+    ecall(HSM_EID, HSM_HART_START, [hartid, start_addr, opaque, 0, 0])
+}
+
+/// Stop the calling hart. Only returns if that failed.
+pub fn hart_stop() -> SbiRet {
+    ecall(HSM_EID, HSM_HART_STOP, [0; 5])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+    Unknown(usize),
+}
+
+/// What `hartid` is up to, or the SBI error if it doesn't exist
+pub fn hart_get_status(hartid: usize) -> Result<HartState, isize> {
+    let ret = ecall(HSM_EID, HSM_HART_GET_STATUS, [hartid, 0, 0, 0, 0]);
+    if !ret.is_ok() {
+        return Err(ret.error);
+    }
+    Ok(match ret.value {
+        0 => HartState::Started,
+        1 => HartState::Stopped,
+        2 => HartState::StartPending,
+        3 => HartState::StopPending,
+        4 => HartState::Suspended,
+        5 => HartState::SuspendPending,
+        6 => HartState::ResumePending,
+        s => HartState::Unknown(s),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetReason {
+    None = 0,
+    SystemFailure = 1,
+}
+
+/// Shut down or reboot the machine. Only returns if that failed.
+pub fn system_reset(kind: ResetType, reason: ResetReason) -> SbiRet {
+    ecall(
+        SRST_EID,
+        SRST_SYSTEM_RESET,
+        [kind as usize, reason as usize, 0, 0, 0],
+    )
+}
+
+const CONSOLE_UNPROBED: u8 = 0;
+const CONSOLE_DBCN: u8 = 1;
+const CONSOLE_LEGACY: u8 = 2;
+
+/// Which console call the firmware supports
+static CONSOLE: AtomicU8 = AtomicU8::new(CONSOLE_UNPROBED);
+
+fn console_kind() -> u8 {
+    match CONSOLE.load(Ordering::Relaxed) {
+        CONSOLE_UNPROBED => {
+            let kind = if probe_extension(DBCN_EID) {
+                CONSOLE_DBCN
+            } else {
+                CONSOLE_LEGACY
+            };
+            CONSOLE.store(kind, Ordering::Relaxed);
+            kind
+        }
+        kind => kind,
+    }
+}
+
+/// Write `bytes` to the firmware's debug console. `bytes` must be in the
+/// kernel image or the direct map, the firmware reads it by physical
+/// address.
+pub fn console_write(bytes: &[u8]) {
+    if console_kind() == CONSOLE_DBCN {
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let addr = addr::virt_to_phys(rest.as_ptr() as usize);
+            let ret = ecall(DBCN_EID, DBCN_CONSOLE_WRITE, [rest.len(), addr, 0, 0, 0]);
+            if !ret.is_ok() {
+                return;
+            }
+            // The firmware may write fewer bytes than we asked for
+            rest = &rest[ret.value.min(rest.len())..];
+        }
+    } else {
+        for &b in bytes {
+            ecall(LEGACY_CONSOLE_PUTCHAR_EID, 0, [b as usize, 0, 0, 0, 0]);
+        }
+    }
+}
+
+/// `core::fmt::Write` on top of [`console_write`]
+pub struct DebugConsole;
+
+impl fmt::Write for DebugConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        console_write(s.as_bytes());
+        Ok(())
+    }
+}
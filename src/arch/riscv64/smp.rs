@@ -0,0 +1,148 @@
+//! Bringing up the other harts
+//!
+//! Whichever hart `kinit` runs on (always #0 when we own machine mode) is
+//! the boot hart. Once it has memory and interrupts set up it calls
+//! [`boot_secondaries`], which asks the SBI to start every other hart
+//! through the HSM extension. Under our own machine mode they are the
+//! harts waiting in `hart_parking_lot` (see `boot/asm/boot.s`), under SBI
+//! firmware (`riscv-sbi`) the ones it left stopped.
+//!
+//! Either way they drop into supervisor mode at
+//! `_secondary_supervisor_mode_entry` (by way of the trampoline in the
+//! higher half kernel), run `kinit_hart` and mark themselves online.
+
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super::{csr, mm::addr, percpu, sbi, timer, trap::KERNEL_TRAP_FRAMES, MAX_HARTS};
+use crate::{kernel_param, println};
+
+extern "C" {
+    #[cfg(not(feature = "higher-half"))]
+    fn _secondary_supervisor_mode_entry();
+    /// Turns paging on before going on to `_secondary_supervisor_mode_entry`
+    #[cfg(feature = "higher-half")]
+    fn _secondary_trampoline_entry();
+}
+
+/// How long to wait for the secondaries to report in
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(1);
+
+kernel_param! {
+    /// Most harts to bring up, the boot hart included (`smp=`)
+    static SMP: usize = "smp", MAX_HARTS;
+}
+
+/// Bit `n` is set once hart `n` is running in supervisor mode
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+static BOOT_HARTID: AtomicUsize = AtomicUsize::new(0);
+
+/// `satp` the secondaries should use, i.e. whatever the boot hart runs with
+static BOOT_SATP: AtomicUsize = AtomicUsize::new(0);
+
+/// The hart `kinit` ran on
+pub fn boot_hartid() -> usize {
+    BOOT_HARTID.load(Ordering::Relaxed)
+}
+
+/// Harts running the kernel, the boot hart included
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::Acquire)
+}
+
+pub fn is_online(hart: usize) -> bool {
+    hart < MAX_HARTS && online_harts() & (1 << hart) != 0
+}
+
+/// Ask the SBI to start every stopped hart, or as many as `smp=` allows.
+/// Harts that haven't made it
+/// to the parking lot by the time this is called stay where they are.
+fn start_secondaries() -> usize {
+    #[cfg(not(feature = "higher-half"))]
+    let entry = _secondary_supervisor_mode_entry as usize;
+    #[cfg(feature = "higher-half")]
+    let entry = _secondary_trampoline_entry as usize;
+    // The hart starts with paging off
+    let entry = addr::virt_to_phys(entry);
+    let wanted = SMP.get().saturating_sub(1);
+    let mut started: usize = 0;
+    for hart in (0..MAX_HARTS).filter(|&h| h != boot_hartid()) {
+        if started.count_ones() as usize >= wanted {
+            break;
+        }
+        if sbi::hart_get_status(hart) != Ok(sbi::HartState::Stopped) {
+            continue;
+        }
+        let ret = sbi::hart_start(hart, entry, 0);
+        if ret.is_ok() {
+            started |= 1 << hart;
+        } else {
+            println!("Failed to start hart {}: {}", hart, ret.error);
+        }
+    }
+    started
+}
+
+/// Start the other harts and wait until they are all online.
+pub fn boot_secondaries() {
+    BOOT_SATP.store(csr::satp::read(), Ordering::Release);
+
+    let started = start_secondaries();
+    if started == 0 {
+        println!("No secondary harts to bring up");
+        return;
+    }
+
+    let deadline = timer::now() + timer::to_timebase(ONLINE_TIMEOUT);
+    while online_harts() & started != started && timer::now() < deadline {
+        core::hint::spin_loop();
+    }
+
+    let missing = started & !online_harts();
+    if missing != 0 {
+        println!("Harts {:#b} did not come online", missing);
+    }
+    println!("{} harts online", online_harts().count_ones());
+}
+
+/// Set up the hart's per-CPU block and point `sscratch` at its trap frame.
+fn init_percpu(hartid: usize) {
+    unsafe {
+        let frame = addr_of_mut!(KERNEL_TRAP_FRAMES[hartid]);
+        percpu::init(hartid, frame);
+        csr::sscratch::write(frame as usize);
+    }
+}
+
+/// First thing `kinit` does, nothing may trap before this.
+pub(super) fn init_boot_hart(hartid: usize) {
+    assert!(hartid < MAX_HARTS, "booted on hart {}", hartid);
+    BOOT_HARTID.store(hartid, Ordering::Relaxed);
+    init_percpu(hartid);
+    mark_online(hartid);
+}
+
+/// Per-hart setup the assembly can't do, run first thing on each
+/// secondary. Sets up the hart's per-CPU block and trap frame and
+/// switches to the kernel's address space.
+pub(super) fn init_hart(hartid: usize) {
+    init_percpu(hartid);
+    unsafe {
+        csr::satp::write(BOOT_SATP.load(Ordering::Acquire));
+        core::arch::asm!("sfence.vma");
+    }
+}
+
+/// Let the boot hart know this hart is up.
+pub(super) fn mark_online(hartid: usize) {
+    ONLINE_HARTS.fetch_or(1 << hartid, Ordering::Release);
+}
+
+/// Take this hart out of the online mask before it stops for good.
+pub(super) fn mark_offline(hartid: usize) {
+    ONLINE_HARTS.fetch_and(!(1 << hartid), Ordering::Release);
+}
@@ -0,0 +1,126 @@
+//! The supervisor timer
+//!
+//! Time is read from the `time` CSR, which mirrors the CLINT's `mtime`.
+//! Every hart that calls [`init`] gets a timer interrupt [`TICK_HZ`] times
+//! a second, which advances [`ticks`] and calls the registered tick
+//! handler (the scheduler, once we have one).
+
+use core::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super::{irq, sbi, smp};
+use crate::{fdt, percpu};
+
+/// Frequency of the `time` CSR on QEMU's `virt` machine, used if the
+/// device tree doesn't tell us
+pub const DEFAULT_TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// How many scheduler ticks we want per second
+pub const TICK_HZ: u64 = 100;
+
+/// Frequency of the `time` CSR
+static TIMEBASE_FREQ: AtomicU64 = AtomicU64::new(DEFAULT_TIMEBASE_FREQ);
+
+/// Number of ticks since the boot hart started its timer
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// `fn(hartid)` called on every tick, or 0 if none is registered
+static TICK_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// The current value of the `time` CSR
+#[inline]
+pub fn now() -> u64 {
+    let t: u64;
+    unsafe { core::arch::asm!("rdtime {}", out(reg) t) };
+    t
+}
+
+/// Time since the timer started counting
+pub fn uptime() -> Duration {
+    from_timebase(now())
+}
+
+/// Number of timer ticks since boot. Only the boot hart advances this,
+/// so it is monotonic and doesn't depend on how many harts are up.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Frequency of the `time` CSR, in Hz
+pub fn timebase_freq() -> u64 {
+    TIMEBASE_FREQ.load(Ordering::Relaxed)
+}
+
+fn tick_interval() -> u64 {
+    timebase_freq() / TICK_HZ
+}
+
+/// `d` in `time` ticks, saturating at `u64::MAX`
+pub fn to_timebase(d: Duration) -> u64 {
+    let ticks = d.as_nanos().saturating_mul(timebase_freq() as u128) / 1_000_000_000;
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
+
+pub fn from_timebase(t: u64) -> Duration {
+    Duration::from_nanos((t as u128 * 1_000_000_000 / timebase_freq() as u128) as u64)
+}
+
+/// Register `f` to be called on every tick, on every hart.
+pub fn set_tick_handler(f: fn(usize)) {
+    TICK_HANDLER.store(f as usize, Ordering::Release);
+}
+
+/// Read the timebase frequency from the device tree. Runs once on the
+/// boot hart, before anything converts times.
+pub fn probe() {
+    if let Some(freq) = fdt::get().and_then(|fdt| fdt.timebase_frequency()) {
+        TIMEBASE_FREQ.store(freq, Ordering::Relaxed);
+    }
+}
+
+/// Start the periodic tick on the calling hart.
+pub fn init() {
+    sbi::set_timer(now() + tick_interval());
+}
+
+/// Called from the trap handler on a supervisor timer interrupt.
+pub fn handle_interrupt(hartid: usize) {
+    // Programming the next deadline also clears the pending interrupt
+    sbi::set_timer(now() + tick_interval());
+
+    if hartid == smp::boot_hartid() {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+    percpu!(counters).ticks.fetch_add(1, Ordering::Relaxed);
+
+    let handler = TICK_HANDLER.load(Ordering::Acquire);
+    if handler != 0 {
+        let f: fn(usize) = unsafe { core::mem::transmute(handler) };
+        f(hartid);
+    }
+}
+
+/// Spin until `d` has passed without ever giving up the hart.
+pub fn busy_sleep(d: Duration) {
+    let deadline = now().saturating_add(to_timebase(d));
+    while now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Wait for `d` to pass.
+///
+/// With interrupts enabled the hart sleeps in `wfi` between ticks,
+/// otherwise nothing would wake it up again and we fall back to spinning.
+pub fn sleep(d: Duration) {
+    if !irq::irqs_enabled() {
+        return busy_sleep(d);
+    }
+
+    let deadline = now().saturating_add(to_timebase(d));
+    while now() < deadline {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
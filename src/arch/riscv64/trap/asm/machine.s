@@ -0,0 +1,168 @@
+##! Machine trap entry for macaque
+##!
+##! Whenever a hart is running below machine mode, `mscratch` holds the
+##! top of that hart's machine trap stack (`MACHINE_TRAP_STACKS`). While
+##! a machine trap is being handled `mscratch` is zero, so a trap taken
+##! from machine mode itself simply keeps using the current stack. This is
+##! what lets `m_trap_handler` recover from faults it causes on purpose,
+##! such as the `mprv` accesses below.
+##!
+##! The `TrapFrame` is built on the stack with the same layout the
+##! supervisor entry uses.
+.option norvc
+.section .text
+
+.set TRAP_FRAME_SIZE, 304
+.set MSTATUS_MPP_MASK, (0b11 << 11)
+.set MSTATUS_MPRV, (1 << 17)
+
+.global m_trap_vector
+.balign 4
+m_trap_vector:
+		csrrw	sp, mscratch, sp
+		bnez	sp, _m_trap_vector_m_save_gprs
+		# `mscratch` was zero, so we trapped from machine mode.
+		# Carry on with the stack we were already using.
+		csrr	sp, mscratch
+
+_m_trap_vector_m_save_gprs:
+		addi	sp, sp, -TRAP_FRAME_SIZE
+		# x0 is hardwired to zero and the interrupted sp is in mscratch
+		sd		x1, 8(sp)
+		sd		x3, 24(sp)
+		sd		x4, 32(sp)
+		sd		x5, 40(sp)
+		sd		x6, 48(sp)
+		sd		x7, 56(sp)
+		sd		x8, 64(sp)
+		sd		x9, 72(sp)
+		sd		x10, 80(sp)
+		sd		x11, 88(sp)
+		sd		x12, 96(sp)
+		sd		x13, 104(sp)
+		sd		x14, 112(sp)
+		sd		x15, 120(sp)
+		sd		x16, 128(sp)
+		sd		x17, 136(sp)
+		sd		x18, 144(sp)
+		sd		x19, 152(sp)
+		sd		x20, 160(sp)
+		sd		x21, 168(sp)
+		sd		x22, 176(sp)
+		sd		x23, 184(sp)
+		sd		x24, 192(sp)
+		sd		x25, 200(sp)
+		sd		x26, 208(sp)
+		sd		x27, 216(sp)
+		sd		x28, 224(sp)
+		sd		x29, 232(sp)
+		sd		x30, 240(sp)
+		sd		x31, 248(sp)
+
+		csrr	t0, mscratch
+		sd		t0, 16(sp)
+		# Any trap from here on out is a nested one
+		csrw	mscratch, zero
+
+_m_trap_vector_m_save_csrs:
+		csrr	t0, mepc
+		sd		t0, 256(sp)
+		csrr	t0, mstatus
+		sd		t0, 264(sp)
+		csrr	t0, mtval
+		sd		t0, 272(sp)
+		csrr	t0, mcause
+		sd		t0, 280(sp)
+		csrr	t0, mhartid
+		sd		t0, 288(sp)
+
+_m_trap_vector_m_dispatch:
+		mv		a0, sp
+		call	m_trap_handler
+
+_m_trap_vector_m_restore_csrs:
+		ld		t0, 256(sp)
+		csrw	mepc, t0
+		ld		t0, 264(sp)
+		csrw	mstatus, t0
+
+		# If we are going back to a lower privilege mode, our frame
+		# sits at the very top of the machine stack, so hand that
+		# back to mscratch for the next trap.
+		li		t1, MSTATUS_MPP_MASK
+		and		t0, t0, t1
+		beq		t0, t1, _m_trap_vector_m_restore_gprs
+		addi	t0, sp, TRAP_FRAME_SIZE
+		csrw	mscratch, t0
+
+_m_trap_vector_m_restore_gprs:
+		ld		x1, 8(sp)
+		ld		x3, 24(sp)
+		ld		x4, 32(sp)
+		ld		x5, 40(sp)
+		ld		x6, 48(sp)
+		ld		x7, 56(sp)
+		ld		x8, 64(sp)
+		ld		x9, 72(sp)
+		ld		x10, 80(sp)
+		ld		x11, 88(sp)
+		ld		x12, 96(sp)
+		ld		x13, 104(sp)
+		ld		x14, 112(sp)
+		ld		x15, 120(sp)
+		ld		x16, 128(sp)
+		ld		x17, 136(sp)
+		ld		x18, 144(sp)
+		ld		x19, 152(sp)
+		ld		x20, 160(sp)
+		ld		x21, 168(sp)
+		ld		x22, 176(sp)
+		ld		x23, 184(sp)
+		ld		x24, 192(sp)
+		ld		x25, 200(sp)
+		ld		x26, 208(sp)
+		ld		x27, 216(sp)
+		ld		x28, 224(sp)
+		ld		x29, 232(sp)
+		ld		x30, 240(sp)
+		ld		x31, 248(sp)
+		ld		sp, 16(sp)
+
+		mret
+
+# Byte sized accesses made with the privilege and address translation
+# of whoever trapped into machine mode (`mstatus.MPP`).
+#
+# Both return the loaded byte (or nothing) in a0 and 0 in a1 on success.
+# If the access faults, `m_trap_handler` resumes us at
+# `_m_mprv_m_fixup`, which returns 1 in a1 instead.
+.global m_mprv_load_u8
+m_mprv_load_u8:
+		li		t1, MSTATUS_MPRV
+		mv		a1, zero
+		csrs	mstatus, t1
+.global _m_mprv_load_u8_m_access
+_m_mprv_load_u8_m_access:
+		lbu		a0, 0(a0)
+		csrc	mstatus, t1
+		ret
+
+.global m_mprv_store_u8
+m_mprv_store_u8:
+		li		t1, MSTATUS_MPRV
+		mv		a2, a1
+		mv		a1, zero
+		csrs	mstatus, t1
+.global _m_mprv_store_u8_m_access
+_m_mprv_store_u8_m_access:
+		sb		a2, 0(a0)
+		csrc	mstatus, t1
+		ret
+
+.global _m_mprv_m_fixup
+_m_mprv_m_fixup:
+		li		t1, MSTATUS_MPRV
+		csrc	mstatus, t1
+		mv		a0, zero
+		li		a1, 1
+		ret
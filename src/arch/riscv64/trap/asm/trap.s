@@ -0,0 +1,126 @@
+##! Supervisor trap entry for macaque
+##!
+##! The `TrapFrame` (see `trap/mod.rs` for the layout) is built on the
+##! stack of whatever was interrupted, and the handler runs below it. A
+##! trap taken while handling another one, say a fault while printing a
+##! backtrace, gets a frame of its own further down and leaves the first
+##! one alone.
+##!
+##! While a hart runs in supervisor mode, `sscratch` holds a pointer to
+##! that hart's entry in `KERNEL_TRAP_FRAMES`, which is only read for the
+##! hart id and the hart's `PerCpu` block.
+##!
+##! We spill every general purpose register plus the trap CSRs into the
+##! frame and hand it to `s_trap_handler`. The handler is free to modify
+##! the frame (for example to step over an `ebreak`), and whatever it
+##! leaves there is restored before `sret`.
+.option norvc
+.section .text
+
+.set TRAP_FRAME_SIZE, 304
+
+.global s_trap_vector
+# `stvec` is used in direct mode, so the low two bits must be clear
+.balign 4
+s_trap_vector:
+		addi	sp, sp, -TRAP_FRAME_SIZE
+
+_s_trap_vector_s_save_gprs:
+		# x0 is hardwired to zero and sp is saved below
+		sd		x1, 8(sp)
+		sd		x3, 24(sp)
+		sd		x4, 32(sp)
+		sd		x5, 40(sp)
+		sd		x6, 48(sp)
+		sd		x7, 56(sp)
+		sd		x8, 64(sp)
+		sd		x9, 72(sp)
+		sd		x10, 80(sp)
+		sd		x11, 88(sp)
+		sd		x12, 96(sp)
+		sd		x13, 104(sp)
+		sd		x14, 112(sp)
+		sd		x15, 120(sp)
+		sd		x16, 128(sp)
+		sd		x17, 136(sp)
+		sd		x18, 144(sp)
+		sd		x19, 152(sp)
+		sd		x20, 160(sp)
+		sd		x21, 168(sp)
+		sd		x22, 176(sp)
+		sd		x23, 184(sp)
+		sd		x24, 192(sp)
+		sd		x25, 200(sp)
+		sd		x26, 208(sp)
+		sd		x27, 216(sp)
+		sd		x28, 224(sp)
+		sd		x29, 232(sp)
+		sd		x30, 240(sp)
+		sd		x31, 248(sp)
+		addi	t0, sp, TRAP_FRAME_SIZE
+		sd		t0, 16(sp)
+
+_s_trap_vector_s_save_csrs:
+		csrr	t0, sepc
+		sd		t0, 256(sp)
+		csrr	t0, sstatus
+		sd		t0, 264(sp)
+		csrr	t0, stval
+		sd		t0, 272(sp)
+		csrr	t0, scause
+		sd		t0, 280(sp)
+
+# Whatever was interrupted might not have had `tp` pointing at our
+# per-hart block, so put it back. It is restored along with everything
+# else on the way out.
+_s_trap_vector_s_load_percpu:
+		csrr	t1, sscratch
+		ld		t0, 288(t1)
+		sd		t0, 288(sp)
+		ld		tp, 296(t1)
+		sd		tp, 296(sp)
+
+_s_trap_vector_s_dispatch:
+		mv		a0, sp
+		call	s_trap_handler
+
+_s_trap_vector_s_restore_csrs:
+		ld		t0, 256(sp)
+		csrw	sepc, t0
+		ld		t0, 264(sp)
+		csrw	sstatus, t0
+
+_s_trap_vector_s_restore_gprs:
+		ld		x1, 8(sp)
+		ld		x3, 24(sp)
+		ld		x4, 32(sp)
+		ld		x5, 40(sp)
+		ld		x6, 48(sp)
+		ld		x7, 56(sp)
+		ld		x8, 64(sp)
+		ld		x9, 72(sp)
+		ld		x10, 80(sp)
+		ld		x11, 88(sp)
+		ld		x12, 96(sp)
+		ld		x13, 104(sp)
+		ld		x14, 112(sp)
+		ld		x15, 120(sp)
+		ld		x16, 128(sp)
+		ld		x17, 136(sp)
+		ld		x18, 144(sp)
+		ld		x19, 152(sp)
+		ld		x20, 160(sp)
+		ld		x21, 168(sp)
+		ld		x22, 176(sp)
+		ld		x23, 184(sp)
+		ld		x24, 192(sp)
+		ld		x25, 200(sp)
+		ld		x26, 208(sp)
+		ld		x27, 216(sp)
+		ld		x28, 224(sp)
+		ld		x29, 232(sp)
+		ld		x30, 240(sp)
+		ld		x31, 248(sp)
+		ld		sp, 16(sp)
+
+		sret
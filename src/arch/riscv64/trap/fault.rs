@@ -0,0 +1,225 @@
+//! Page fault diagnostics
+//!
+//! We don't demand-page anything yet, so every page fault is a bug,
+//! usually a missing or wrong mapping. Before giving up we walk the
+//! active page table for the faulting address, print every entry on the
+//! way down and work out which one the hart tripped over. [`check`] does
+//! the same walk quietly, to vet a table before switching to it.
+
+use core::fmt;
+
+use super::{Exception, TrapFrame};
+use crate::{
+    arch::{
+        csr,
+        mm::{
+            addr::{PhysAddr, VirtAddr},
+            mode::{self, Mode, SATP_MODE_BARE, SATP_MODE_SHIFT, SATP_PPN_MASK},
+            page::{self, Entry, Table},
+        },
+    },
+    println,
+    util::symbols::Symbolized,
+};
+
+const SSTATUS_SUM: usize = 1 << 18;
+const SSTATUS_MXR: usize = 1 << 19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    fn from_exception(exc: Exception) -> Option<Self> {
+        match exc {
+            Exception::InstructionPageFault => Some(Self::Fetch),
+            Exception::LoadPageFault => Some(Self::Load),
+            Exception::StorePageFault => Some(Self::Store),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fetch => "instruction fetch",
+            Self::Load => "load",
+            Self::Store => "store",
+        })
+    }
+}
+
+/// Why the walk for an address ended in a fault
+#[derive(Debug, Clone, Copy)]
+pub enum Reason {
+    /// `satp` is in bare mode, there is nothing to walk
+    PagingDisabled,
+    /// `satp` is set to a mode other than the one our tables are for
+    UnsupportedMode(usize),
+    /// The bits above the translated ones aren't a sign extension of
+    /// the top one
+    NonCanonical,
+    NotPresent(usize),
+    /// `W` without `R`, which the spec reserves
+    WriteWithoutRead(usize),
+    /// Level 0 pointed at yet another table
+    BranchAtLastLevel,
+    /// A superpage whose PPN isn't aligned to its size
+    MisalignedSuperpage(usize),
+    NotExecutable(usize),
+    NotReadable(usize),
+    ReadOnly(usize),
+    /// Supervisor access to a `U` page
+    UserPage(usize),
+    AccessedClear(usize),
+    DirtyClear(usize),
+    /// The table allows the access, so the hart must have used a stale
+    /// translation
+    Permitted,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::PagingDisabled => write!(f, "paging is disabled (satp is bare)"),
+            Self::UnsupportedMode(m) => {
+                let ours = mode::current();
+                write!(f, "satp mode {} is not {} ({})", m, ours, ours.satp())
+            }
+            Self::NonCanonical => write!(f, "address is not canonical"),
+            Self::NotPresent(l) => write!(f, "not present at level {}", l),
+            Self::WriteWithoutRead(l) => write!(f, "reserved W-without-R entry at level {}", l),
+            Self::BranchAtLastLevel => write!(f, "level 0 entry is not a leaf"),
+            Self::MisalignedSuperpage(l) => write!(f, "misaligned superpage at level {}", l),
+            Self::NotExecutable(l) => write!(f, "fetch from non-executable leaf at level {}", l),
+            Self::NotReadable(l) => write!(f, "read from non-readable leaf at level {}", l),
+            Self::ReadOnly(l) => write!(f, "write to R-only leaf at level {}", l),
+            Self::UserPage(l) => write!(f, "supervisor access to U leaf at level {}", l),
+            Self::AccessedClear(l) => write!(f, "A bit clear on leaf at level {}", l),
+            Self::DirtyClear(l) => write!(f, "D bit clear on written leaf at level {}", l),
+            Self::Permitted => write!(f, "the table permits this access (missing sfence.vma?)"),
+        }
+    }
+}
+
+/// Check a leaf found at `level` against the access that faulted.
+fn check_leaf(leaf: &Entry, level: usize, access: Access, sstatus: usize) -> Reason {
+    // A superpage maps 2^(9 * level) pages, so the low PPN fields must be 0
+    let ppn_mask = (1 << (12 + level * 9)) - 1;
+    if leaf.addr().as_usize() & ppn_mask != 0 {
+        return Reason::MisalignedSuperpage(level);
+    }
+    // With MXR set, executable pages are readable too
+    let readable = leaf.is_readable() || (sstatus & SSTATUS_MXR != 0 && leaf.is_executable());
+    match access {
+        Access::Fetch if !leaf.is_executable() => return Reason::NotExecutable(level),
+        Access::Load if !readable => return Reason::NotReadable(level),
+        Access::Store if !leaf.is_writable() => return Reason::ReadOnly(level),
+        _ => {}
+    }
+    if leaf.is_user() && (access == Access::Fetch || sstatus & SSTATUS_SUM == 0) {
+        return Reason::UserPage(level);
+    }
+    if !leaf.is_accessed() {
+        return Reason::AccessedClear(level);
+    }
+    if access == Access::Store && !leaf.is_dirty() {
+        return Reason::DirtyClear(level);
+    }
+    Reason::Permitted
+}
+
+/// Walk `root` for `vaddr`, printing each entry visited if `verbose`.
+fn walk(root: &Table, vaddr: VirtAddr, access: Access, sstatus: usize, verbose: bool) -> Reason {
+    let mut table = root;
+    for level in (0..=mode::current().root_level()).rev() {
+        let index = vaddr.vpn(level);
+        let entry = &table.entries[index];
+        if verbose {
+            println!(
+                "  L{} [{:>3}] @ {:#x}: {:#018x} {} -> {}",
+                level,
+                index,
+                entry as *const Entry as usize,
+                entry.get_entry(),
+                entry,
+                entry.addr()
+            );
+        }
+
+        if entry.is_invalid() {
+            return Reason::NotPresent(level);
+        }
+        if entry.is_writable() && !entry.is_readable() {
+            return Reason::WriteWithoutRead(level);
+        }
+        if entry.is_leaf() {
+            return check_leaf(entry, level, access, sstatus);
+        }
+        if level == 0 {
+            break;
+        }
+        table = unsafe { &*entry.addr().to_virt().as_ptr::<Table>() };
+    }
+    Reason::BranchAtLastLevel
+}
+
+/// Would a supervisor `access` to `vaddr` go through with `root` as the
+/// root table? Ignores `SUM` and `MXR`, as if `sstatus` were clear.
+pub fn check(root: &Table, vaddr: VirtAddr, access: Access) -> Result<(), Reason> {
+    if !mode::current().is_canonical(vaddr.as_usize()) {
+        return Err(Reason::NonCanonical);
+    }
+    match walk(root, vaddr, access, 0, false) {
+        Reason::Permitted => Ok(()),
+        reason => Err(reason),
+    }
+}
+
+/// Explain the page fault in `frame`.
+///
+/// The tables are read through the direct map, so this works as long as
+/// that is mapped.
+pub fn report(frame: &TrapFrame, exc: Exception) {
+    let Some(access) = Access::from_exception(exc) else {
+        return;
+    };
+    let vaddr = frame.tval;
+    let satp = csr::satp::read();
+    let sstatus = frame.status;
+
+    println!(
+        "hart {}: page fault: {} at {:#x}, epc {}",
+        frame.hartid,
+        access,
+        vaddr,
+        Symbolized(frame.epc)
+    );
+    println!("  satp: {:#018x}", satp);
+
+    let satp_mode = satp >> SATP_MODE_SHIFT;
+    let reason = if satp_mode == SATP_MODE_BARE {
+        Reason::PagingDisabled
+    } else if Mode::from_satp(satp_mode) != Some(mode::current()) {
+        Reason::UnsupportedMode(satp_mode)
+    } else if !mode::current().is_canonical(vaddr) {
+        Reason::NonCanonical
+    } else {
+        let root = PhysAddr::new((satp & SATP_PPN_MASK) << 12).to_virt();
+        let root = unsafe { &*root.as_ptr::<Table>() };
+        let reason = walk(root, VirtAddr::new(vaddr), access, sstatus, true);
+        // `virt_to_phys` runs off the end of the walk on a level 0 branch
+        if !matches!(reason, Reason::BranchAtLastLevel) {
+            match page::translate(root, VirtAddr::new(vaddr)) {
+                Some(paddr) => println!("  {:#x} translates to {}", vaddr, paddr),
+                None => println!("  {:#x} has no translation", vaddr),
+            }
+        }
+        reason
+    };
+    println!("  reason: {}", reason);
+}
@@ -0,0 +1,287 @@
+//! The SBI implementation machine mode provides when we boot ourselves
+//!
+//! With `-bios none` there is no firmware underneath us, so machine mode
+//! answers supervisor mode's `ecall`s itself. This way supervisor mode
+//! talks to the same interface (see `arch::sbi`) whether it was booted by
+//! `boot.s` or by real SBI firmware, and never has to touch the CLINT or
+//! any other machine level state.
+//!
+//! Only what the kernel uses is implemented: the base extension, TIME,
+//! IPI, RFENCE, HSM start/stop/status, SRST and the legacy console
+//! putchar.
+//!
+//! Harts that aren't running supervisor code wait in `hart_parking_lot`
+//! (see `boot/asm/boot.s`) for [`hart_start`] to fill in their slots in
+//! `HSM_START_ADDR` and `HSM_START_OPAQUE` and move them to
+//! `HSM_START_PENDING`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::TrapFrame;
+use crate::{
+    arch::{
+        csr,
+        sbi::{self, SbiRet},
+        trap::Interrupt,
+        MAX_HARTS,
+    },
+    drivers::{clint::CLINT, serial, sifive_test::SIFIVE_TEST},
+};
+
+/// We aren't one of the registered implementations, this spells `"MACQ"`
+const IMPL_ID: usize = 0x4d41_4351;
+/// Bumped whenever what we implement changes
+const IMPL_VERSION: usize = 1;
+/// The version of the spec we (mostly) follow, v1.0
+const SPEC_VERSION: usize = 1 << 24;
+
+// Hart states as `hart_get_status` reports them. `boot.s` has its own
+// copies of these.
+const HSM_STARTED: usize = 0;
+const HSM_STOPPED: usize = 1;
+const HSM_START_PENDING: usize = 2;
+/// Not a state in the spec: the hart never made it to the parking lot
+const HSM_ABSENT: usize = usize::MAX;
+
+/// Where each hart is in its lifecycle. `_start` marks the boot hart as
+/// started and the parking lot marks everyone else stopped.
+#[no_mangle]
+static HSM_STATE: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ABSENT: AtomicUsize = AtomicUsize::new(HSM_ABSENT);
+    [ABSENT; MAX_HARTS]
+};
+
+/// `start_addr` and `opaque` of the pending `hart_start` for each hart
+#[no_mangle]
+static HSM_START_ADDR: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+    [EMPTY; MAX_HARTS]
+};
+#[no_mangle]
+static HSM_START_OPAQUE: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+    [EMPTY; MAX_HARTS]
+};
+
+// What a hart has been sent a machine software interrupt for
+const PENDING_SSIP: usize = 1 << 0;
+const PENDING_FENCE_I: usize = 1 << 1;
+const PENDING_SFENCE_VMA: usize = 1 << 2;
+
+static PENDING: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+    [EMPTY; MAX_HARTS]
+};
+
+extern "C" {
+    fn hart_parking_lot() -> !;
+}
+
+const fn ret(error: isize, value: usize) -> SbiRet {
+    SbiRet { error, value }
+}
+
+const SUCCESS: SbiRet = ret(sbi::SBI_SUCCESS, 0);
+
+/// Service an `ecall` from supervisor mode. See `arch::sbi` for the
+/// calling convention.
+pub(super) fn handle_ecall(frame: &mut TrapFrame) -> SbiRet {
+    let (eid, fid) = (frame.regs[17], frame.regs[16]);
+    let (a0, a1, a2) = (frame.regs[10], frame.regs[11], frame.regs[12]);
+    match (eid, fid) {
+        (sbi::BASE_EID, sbi::BASE_GET_SPEC_VERSION) => ret(sbi::SBI_SUCCESS, SPEC_VERSION),
+        (sbi::BASE_EID, sbi::BASE_GET_IMPL_ID) => ret(sbi::SBI_SUCCESS, IMPL_ID),
+        (sbi::BASE_EID, sbi::BASE_GET_IMPL_VERSION) => ret(sbi::SBI_SUCCESS, IMPL_VERSION),
+        (sbi::BASE_EID, sbi::BASE_PROBE_EXTENSION) => {
+            ret(sbi::SBI_SUCCESS, implements(a0) as usize)
+        }
+        (sbi::TIME_EID, sbi::TIME_SET_TIMER) => set_timer(frame.hartid, a0 as u64),
+        (sbi::IPI_EID, sbi::IPI_SEND_IPI) => send_ipi(a0, a1),
+        (sbi::RFENCE_EID, sbi::RFENCE_REMOTE_FENCE_I) => {
+            remote_fence(frame.hartid, a0, a1, PENDING_FENCE_I)
+        }
+        // Flushing everything is always allowed, so ignore the range
+        (sbi::RFENCE_EID, sbi::RFENCE_REMOTE_SFENCE_VMA) => {
+            remote_fence(frame.hartid, a0, a1, PENDING_SFENCE_VMA)
+        }
+        (sbi::HSM_EID, sbi::HSM_HART_START) => hart_start(a0, a1, a2),
+        (sbi::HSM_EID, sbi::HSM_HART_STOP) => hart_stop(frame.hartid),
+        (sbi::HSM_EID, sbi::HSM_HART_GET_STATUS) => hart_get_status(a0),
+        (sbi::SRST_EID, sbi::SRST_SYSTEM_RESET) => system_reset(a0, a1),
+        (sbi::LEGACY_CONSOLE_PUTCHAR_EID, _) => {
+            serial::write_bytes(&[a0 as u8]);
+            SUCCESS
+        }
+        _ => ret(sbi::SBI_ERR_NOT_SUPPORTED, 0),
+    }
+}
+
+fn implements(eid: usize) -> bool {
+    matches!(
+        eid,
+        sbi::BASE_EID
+            | sbi::TIME_EID
+            | sbi::IPI_EID
+            | sbi::RFENCE_EID
+            | sbi::HSM_EID
+            | sbi::SRST_EID
+            | sbi::LEGACY_CONSOLE_PUTCHAR_EID
+    )
+}
+
+fn set_timer(hartid: usize, stime_value: u64) -> SbiRet {
+    CLINT.set_mtimecmp(hartid, stime_value);
+    unsafe {
+        csr::mip::clear(Interrupt::SupervisorTimer.mask());
+        csr::mie::set(Interrupt::MachineTimer.mask());
+    }
+    SUCCESS
+}
+
+/// Turn an SBI `hart_mask`/`hart_mask_base` pair into a plain mask of
+/// harts. Harts we have no room for are quietly dropped.
+fn harts(mask: usize, base: usize) -> Result<usize, SbiRet> {
+    let (mask, base) = if base == sbi::HART_MASK_BASE_ALL {
+        (usize::MAX, 0)
+    } else {
+        (mask, base)
+    };
+    if mask != 0 && base >= MAX_HARTS {
+        return Err(ret(sbi::SBI_ERR_INVALID_PARAM, 0));
+    }
+    Ok((0..usize::BITS as usize)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| base + bit)
+        .take_while(|&hart| hart < MAX_HARTS)
+        .fold(0, |harts, hart| harts | 1 << hart))
+}
+
+/// The started harts in `mask`. Stopped harts can't take requests, waking
+/// them is `hart_start`'s job.
+fn started(mask: usize) -> impl Iterator<Item = usize> {
+    (0..MAX_HARTS).filter(move |&hart| {
+        mask & (1 << hart) != 0 && HSM_STATE[hart].load(Ordering::Acquire) == HSM_STARTED
+    })
+}
+
+/// Ask each started hart in `mask` to do `what` in its machine software
+/// interrupt handler. Returns the harts asked.
+fn post(mask: usize, what: usize) -> usize {
+    started(mask).fold(0, |asked, hart| {
+        PENDING[hart].fetch_or(what, Ordering::Release);
+        CLINT.set_msip(hart, true);
+        asked | 1 << hart
+    })
+}
+
+fn send_ipi(mask: usize, base: usize) -> SbiRet {
+    match harts(mask, base) {
+        Ok(mask) => {
+            post(mask, PENDING_SSIP);
+            SUCCESS
+        }
+        Err(e) => e,
+    }
+}
+
+/// Run `fence` on the harts in `mask` and wait until they all have.
+fn remote_fence(hartid: usize, mask: usize, base: usize, fence: usize) -> SbiRet {
+    let mask = match harts(mask, base) {
+        Ok(mask) => mask,
+        Err(e) => return e,
+    };
+    if mask & (1 << hartid) != 0 {
+        do_fences(fence);
+    }
+    let others = post(mask & !(1 << hartid), fence);
+    // Someone may be waiting on us in the same way, with machine
+    // interrupts off. Keep doing what they ask so neither side hangs.
+    while started(others).any(|hart| PENDING[hart].load(Ordering::Acquire) & fence != 0) {
+        do_fences(PENDING[hartid].fetch_and(PENDING_SSIP, Ordering::AcqRel));
+        core::hint::spin_loop();
+    }
+    SUCCESS
+}
+
+fn do_fences(fences: usize) {
+    if fences & PENDING_FENCE_I != 0 {
+        unsafe { core::arch::asm!("fence.i") };
+    }
+    if fences & PENDING_SFENCE_VMA != 0 {
+        unsafe { core::arch::asm!("sfence.vma") };
+    }
+}
+
+/// Machine software interrupt: do whatever other harts asked us to.
+pub(super) fn handle_software_interrupt(hartid: usize) {
+    // Clear the interrupt first so a request posted after we've looked
+    // raises it again instead of being lost.
+    CLINT.set_msip(hartid, false);
+    let pending = PENDING[hartid].swap(0, Ordering::AcqRel);
+    do_fences(pending);
+    if pending & PENDING_SSIP != 0 {
+        unsafe { csr::mip::set(Interrupt::SupervisorSoftware.mask()) };
+    }
+}
+
+fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+    if hartid >= MAX_HARTS {
+        return ret(sbi::SBI_ERR_INVALID_PARAM, 0);
+    }
+    HSM_START_ADDR[hartid].store(start_addr, Ordering::Relaxed);
+    HSM_START_OPAQUE[hartid].store(opaque, Ordering::Relaxed);
+    match HSM_STATE[hartid].compare_exchange(
+        HSM_STOPPED,
+        HSM_START_PENDING,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    ) {
+        Ok(_) => {
+            CLINT.set_msip(hartid, true);
+            SUCCESS
+        }
+        Err(HSM_ABSENT) => ret(sbi::SBI_ERR_INVALID_PARAM, 0),
+        Err(_) => ret(sbi::SBI_ERR_ALREADY_AVAILABLE, 0),
+    }
+}
+
+/// Send the calling hart back to the parking lot. Stopping can't fail,
+/// so this never returns.
+fn hart_stop(hartid: usize) -> SbiRet {
+    // Nothing should follow us into the parking lot
+    CLINT.set_mtimecmp(hartid, u64::MAX);
+    PENDING[hartid].store(0, Ordering::Relaxed);
+    unsafe {
+        csr::mie::clear(Interrupt::MachineTimer.mask());
+        csr::mip::clear(Interrupt::SupervisorTimer.mask() | Interrupt::SupervisorSoftware.mask());
+        // The parking lot resets `mscratch`, so the trap frame we're
+        // standing on is simply forgotten
+        hart_parking_lot()
+    }
+}
+
+fn hart_get_status(hartid: usize) -> SbiRet {
+    match HSM_STATE.get(hartid).map(|s| s.load(Ordering::Acquire)) {
+        None | Some(HSM_ABSENT) => ret(sbi::SBI_ERR_INVALID_PARAM, 0),
+        Some(state) => ret(sbi::SBI_SUCCESS, state),
+    }
+}
+
+fn system_reset(kind: usize, reason: usize) -> SbiRet {
+    const SHUTDOWN: usize = sbi::ResetType::Shutdown as usize;
+    const COLD_REBOOT: usize = sbi::ResetType::ColdReboot as usize;
+    const WARM_REBOOT: usize = sbi::ResetType::WarmReboot as usize;
+
+    match kind {
+        SHUTDOWN if reason == sbi::ResetReason::None as usize => SIFIVE_TEST.pass(),
+        SHUTDOWN => SIFIVE_TEST.fail(reason as u16),
+        COLD_REBOOT | WARM_REBOOT => SIFIVE_TEST.reset(),
+        _ => return ret(sbi::SBI_ERR_INVALID_PARAM, 0),
+    }
+    // The write should have taken the machine down with it
+    ret(sbi::SBI_ERR_FAILED, 0)
+}
@@ -0,0 +1,125 @@
+//! Machine mode trap handling
+//!
+//! Almost everything is delegated to supervisor mode by `boot.s`. What is
+//! left lands here: the machine timer and software interrupts, which we
+//! forward to supervisor mode (and raise on behalf of supervisor mode),
+//! misaligned loads and stores, which we emulate, and `ecall`s from
+//! supervisor mode, which [`firmware`] answers. Anything else is fatal,
+//! but we say so before parking the hart.
+
+use super::{firmware, misaligned, Exception, Interrupt, Trap, TrapFrame};
+use crate::{
+    arch::{backtrace, csr, MAX_HARTS},
+    drivers::serial,
+    println,
+};
+
+/// Size of each hart's machine trap stack.
+/// `boot.s` has its own copy of this as `M_TRAP_STACK_SIZE`.
+pub const MACHINE_TRAP_STACK_SIZE: usize = 0x4000;
+
+#[repr(C, align(16))]
+pub struct MachineTrapStack([u8; MACHINE_TRAP_STACK_SIZE]);
+
+/// `boot.s` points `mscratch` at the top of the slot for each hart.
+#[no_mangle]
+pub static mut MACHINE_TRAP_STACKS: [MachineTrapStack; MAX_HARTS] = {
+    const EMPTY: MachineTrapStack = MachineTrapStack([0; MACHINE_TRAP_STACK_SIZE]);
+    [EMPTY; MAX_HARTS]
+};
+
+const MSTATUS_MPP_SHIFT: usize = 11;
+const MSTATUS_MPP_MASK: usize = 0b11 << MSTATUS_MPP_SHIFT;
+
+extern "C" {
+    fn _m_mprv_load_u8_m_access();
+    fn _m_mprv_store_u8_m_access();
+    fn _m_mprv_m_fixup();
+}
+
+/// The privilege mode a trap was taken from, as recorded in `mstatus.MPP`
+fn previous_mode(frame: &TrapFrame) -> &'static str {
+    match (frame.status & MSTATUS_MPP_MASK) >> MSTATUS_MPP_SHIFT {
+        0b00 => "user",
+        0b01 => "supervisor",
+        0b11 => "machine",
+        _ => "reserved",
+    }
+}
+
+/// Called by `m_trap_vector` with the frame it built on the machine stack.
+#[no_mangle]
+extern "C" fn m_trap_handler(frame: &mut TrapFrame) {
+    match Trap::from_cause(frame.cause) {
+        Trap::Interrupt(irq) => handle_interrupt(frame, irq),
+        Trap::Exception(exc) => handle_exception(frame, exc),
+    }
+}
+
+fn handle_interrupt(frame: &mut TrapFrame, irq: Interrupt) {
+    match irq {
+        Interrupt::MachineTimer => unsafe {
+            // Supervisor mode can't see the machine timer, so raise its
+            // timer interrupt instead. The machine timer stays masked
+            // until supervisor mode asks for the next one.
+            csr::mie::clear(Interrupt::MachineTimer.mask());
+            csr::mip::set(Interrupt::SupervisorTimer.mask());
+        },
+        Interrupt::MachineSoftware => firmware::handle_software_interrupt(frame.hartid),
+        _ => {
+            println!(
+                "hart {}: unexpected machine interrupt {:?}, masking it",
+                frame.hartid, irq
+            );
+            unsafe { csr::mie::clear(irq.mask()) };
+        }
+    }
+}
+
+fn handle_exception(frame: &mut TrapFrame, exc: Exception) {
+    match exc {
+        Exception::LoadMisaligned | Exception::StoreMisaligned => {
+            if let Err(e) = misaligned::emulate(frame) {
+                fatal(frame, exc, e.as_str());
+            }
+        }
+        Exception::SupervisorEcall => {
+            let ret = firmware::handle_ecall(frame);
+            frame.regs[10] = ret.error as usize;
+            frame.regs[11] = ret.value;
+            frame.epc += 4;
+        }
+        Exception::LoadPageFault
+        | Exception::LoadAccessFault
+        | Exception::StorePageFault
+        | Exception::StoreAccessFault
+            if is_mprv_access(frame.epc) =>
+        {
+            // One of our own accesses on behalf of a lower mode faulted.
+            // Let the caller know instead of taking the machine down.
+            frame.epc = _m_mprv_m_fixup as usize;
+        }
+        _ => fatal(frame, exc, "not handled in machine mode"),
+    }
+}
+
+fn is_mprv_access(epc: usize) -> bool {
+    epc == _m_mprv_load_u8_m_access as usize || epc == _m_mprv_store_u8_m_access as usize
+}
+
+/// Report a trap we can't recover from and park the hart.
+fn fatal(frame: &TrapFrame, exc: Exception, reason: &str) -> ! {
+    println!(
+        "hart {}: machine trap {:?} from {} mode: {}",
+        frame.hartid,
+        exc,
+        previous_mode(frame),
+        reason
+    );
+    println!("{}", frame);
+    backtrace::print_from(frame.epc, frame.regs[8]);
+    serial::flush();
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
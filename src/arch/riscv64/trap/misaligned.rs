@@ -0,0 +1,163 @@
+//! Emulation of misaligned loads and stores
+//!
+//! Harts are allowed to trap on any access that isn't naturally aligned.
+//! When that happens we decode the instruction at `mepc`, redo the access
+//! one byte at a time with the trapping mode's view of memory, and step
+//! over the instruction.
+//!
+//! Only integer loads and stores are handled. Floating point and atomic
+//! accesses are reported as unsupported.
+
+use super::TrapFrame;
+
+extern "C" {
+    fn m_mprv_load_u8(addr: usize) -> MprvResult;
+    fn m_mprv_store_u8(addr: usize, val: u8) -> MprvResult;
+}
+
+/// What `m_mprv_load_u8`/`m_mprv_store_u8` hand back in `a0`/`a1`
+#[repr(C)]
+struct MprvResult {
+    val: usize,
+    faulted: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum EmulationError {
+    /// The instruction at `mepc` could not be read
+    InstructionFetch,
+    /// Not an instruction we know how to emulate
+    Unsupported(u32),
+    /// The data access itself faulted
+    Access(usize),
+}
+
+impl EmulationError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InstructionFetch => "could not fetch the trapping instruction",
+            Self::Unsupported(_) => "unsupported misaligned instruction",
+            Self::Access(_) => "misaligned access faulted during emulation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Load { rd: usize, width: usize, signed: bool },
+    Store { rs2: usize, width: usize },
+}
+
+fn load_u8(addr: usize) -> Option<u8> {
+    let res = unsafe { m_mprv_load_u8(addr) };
+    (res.faulted == 0).then_some(res.val as u8)
+}
+
+fn store_u8(addr: usize, val: u8) -> Option<()> {
+    let res = unsafe { m_mprv_store_u8(addr, val) };
+    (res.faulted == 0).then_some(())
+}
+
+/// Fetch the instruction at `pc`, returning it and its length.
+fn fetch(pc: usize) -> Option<(u32, usize)> {
+    let lo = load_u8(pc)? as u32 | (load_u8(pc + 1)? as u32) << 8;
+    if lo & 0b11 != 0b11 {
+        return Some((lo, 2));
+    }
+    let hi = load_u8(pc + 2)? as u32 | (load_u8(pc + 3)? as u32) << 8;
+    Some((lo | hi << 16, 4))
+}
+
+fn decode(insn: u32, len: usize) -> Option<Access> {
+    if len == 4 {
+        let funct3 = (insn >> 12) & 0b111;
+        let rd = ((insn >> 7) & 0x1f) as usize;
+        let rs2 = ((insn >> 20) & 0x1f) as usize;
+        return match insn & 0x7f {
+            // LOAD
+            0x03 => {
+                let (width, signed) = match funct3 {
+                    0b000 => (1, true),
+                    0b001 => (2, true),
+                    0b010 => (4, true),
+                    0b011 => (8, false),
+                    0b100 => (1, false),
+                    0b101 => (2, false),
+                    0b110 => (4, false),
+                    _ => return None,
+                };
+                Some(Access::Load { rd, width, signed })
+            }
+            // STORE
+            0x23 => {
+                let width = match funct3 {
+                    0b000 => 1,
+                    0b001 => 2,
+                    0b010 => 4,
+                    0b011 => 8,
+                    _ => return None,
+                };
+                Some(Access::Store { rs2, width })
+            }
+            _ => None,
+        };
+    }
+
+    // Compressed instructions. The three bit register fields of
+    // quadrant 0 encode x8-x15.
+    let funct3 = (insn >> 13) & 0b111;
+    let rd_prime = 8 + ((insn >> 2) & 0b111) as usize;
+    let rd_full = ((insn >> 7) & 0x1f) as usize;
+    let rs2_full = ((insn >> 2) & 0x1f) as usize;
+    match (insn & 0b11, funct3) {
+        // C.LW / C.LD
+        (0b00, 0b010) => Some(Access::Load { rd: rd_prime, width: 4, signed: true }),
+        (0b00, 0b011) => Some(Access::Load { rd: rd_prime, width: 8, signed: false }),
+        // C.SW / C.SD
+        (0b00, 0b110) => Some(Access::Store { rs2: rd_prime, width: 4 }),
+        (0b00, 0b111) => Some(Access::Store { rs2: rd_prime, width: 8 }),
+        // C.LWSP / C.LDSP
+        (0b10, 0b010) => Some(Access::Load { rd: rd_full, width: 4, signed: true }),
+        (0b10, 0b011) => Some(Access::Load { rd: rd_full, width: 8, signed: false }),
+        // C.SWSP / C.SDSP
+        (0b10, 0b110) => Some(Access::Store { rs2: rs2_full, width: 4 }),
+        (0b10, 0b111) => Some(Access::Store { rs2: rs2_full, width: 8 }),
+        _ => None,
+    }
+}
+
+/// Emulate the misaligned access described by `frame` and step past it.
+pub fn emulate(frame: &mut TrapFrame) -> Result<(), EmulationError> {
+    let (insn, len) = fetch(frame.epc).ok_or(EmulationError::InstructionFetch)?;
+    let access = decode(insn, len).ok_or(EmulationError::Unsupported(insn))?;
+    // `mtval` holds the effective address, which saves us from decoding
+    // the immediate.
+    let addr = frame.tval;
+
+    match access {
+        Access::Load { rd, width, signed } => {
+            let mut val: usize = 0;
+            for i in 0..width {
+                let b = load_u8(addr + i).ok_or(EmulationError::Access(addr + i))?;
+                val |= (b as usize) << (i * 8);
+            }
+            if signed && width < 8 {
+                let shift = usize::BITS as usize - width * 8;
+                val = (((val << shift) as isize) >> shift) as usize;
+            }
+            if rd != 0 {
+                frame.regs[rd] = val;
+            }
+        }
+        Access::Store { rs2, width } => {
+            let val = frame.regs[rs2];
+            for i in 0..width {
+                store_u8(addr + i, (val >> (i * 8)) as u8)
+                    .ok_or(EmulationError::Access(addr + i))?;
+            }
+        }
+    }
+
+    frame.epc += len;
+    Ok(())
+}
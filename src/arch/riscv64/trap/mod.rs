@@ -0,0 +1,289 @@
+//! Trap handling for macaque
+//!
+//! Supervisor traps enter through `s_trap_vector` (see `asm/trap.s`),
+//! which saves the interrupted context into the hart's [`TrapFrame`] and
+//! calls [`s_trap_handler`] with it. The handler decodes `scause` into a
+//! [`Trap`] and dispatches on it.
+//!
+//! The few traps that stay in machine mode go through `m_trap_vector`
+//! (see `asm/machine.s`) and are handled in `machine`, unless we were
+//! built with `riscv-sbi` and the firmware takes care of those.
+
+use core::{arch::global_asm, fmt, mem::offset_of, sync::atomic::Ordering};
+
+use crate::{drivers::plic, percpu, println};
+
+use super::{backtrace, csr, ipi, timer, MAX_HARTS};
+
+pub mod fault;
+#[cfg(not(feature = "riscv-sbi"))]
+mod firmware;
+#[cfg(not(feature = "riscv-sbi"))]
+pub mod machine;
+#[cfg(not(feature = "riscv-sbi"))]
+mod misaligned;
+
+global_asm!(include_str!("asm/trap.s"));
+#[cfg(not(feature = "riscv-sbi"))]
+global_asm!(include_str!("asm/machine.s"));
+
+/// The state of a hart at the moment it trapped.
+///
+/// `asm/trap.s` addresses this structure by hard coded offsets,
+/// so the layout must not change without updating the assembly.
+#[repr(C)]
+pub struct TrapFrame {
+    /// `x0` through `x31`. `x0` is never written.
+    pub regs: [usize; 32],
+    /// The address of the instruction that trapped (or was interrupted).
+    /// Whatever is in here when the handler returns is where we resume.
+    pub epc: usize,
+    pub status: usize,
+    pub tval: usize,
+    pub cause: usize,
+    pub hartid: usize,
+    /// The hart's `percpu::PerCpu` block, which `s_trap_vector` loads
+    /// into `tp`
+    pub percpu: usize,
+}
+
+const _: () = {
+    assert!(offset_of!(TrapFrame, epc) == 256);
+    assert!(offset_of!(TrapFrame, status) == 264);
+    assert!(offset_of!(TrapFrame, tval) == 272);
+    assert!(offset_of!(TrapFrame, cause) == 280);
+    assert!(offset_of!(TrapFrame, hartid) == 288);
+    assert!(offset_of!(TrapFrame, percpu) == 296);
+    // The trap entries reserve 304 bytes to keep the stack 16-byte aligned
+    assert!(core::mem::size_of::<TrapFrame>() <= 304);
+};
+
+impl TrapFrame {
+    pub const fn zero() -> Self {
+        Self {
+            regs: [0; 32],
+            epc: 0,
+            status: 0,
+            tval: 0,
+            cause: 0,
+            hartid: 0,
+            percpu: 0,
+        }
+    }
+}
+
+/// ABI names of `x0`..`x31`, used when dumping a frame.
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "hart {}  epc: {:#018x}  status: {:#018x}",
+            self.hartid, self.epc, self.status
+        )?;
+        writeln!(
+            f,
+            "cause: {:#018x}  tval: {:#018x}",
+            self.cause, self.tval
+        )?;
+        for (i, (name, val)) in REG_NAMES.iter().zip(self.regs.iter()).enumerate().skip(1) {
+            write!(f, "{:>4}: {:#018x}", name, val)?;
+            if i % 4 == 3 {
+                writeln!(f)?;
+            } else {
+                write!(f, "  ")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One per hart, and `sscratch` points at the entry belonging to the hart
+/// it is read on. `s_trap_vector` only takes `hartid` and `percpu` from
+/// it, the frames of actual traps are built on the stack.
+#[no_mangle]
+pub static mut KERNEL_TRAP_FRAMES: [TrapFrame; MAX_HARTS] = {
+    const EMPTY: TrapFrame = TrapFrame::zero();
+    [EMPTY; MAX_HARTS]
+};
+
+/// The high bit of `xcause` is set when the trap was caused by an interrupt.
+const CAUSE_INTERRUPT_BIT: usize = 1 << (usize::BITS - 1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+    Unknown(usize),
+}
+
+impl Interrupt {
+    pub fn from_code(code: usize) -> Self {
+        match code {
+            1 => Self::SupervisorSoftware,
+            3 => Self::MachineSoftware,
+            5 => Self::SupervisorTimer,
+            7 => Self::MachineTimer,
+            9 => Self::SupervisorExternal,
+            11 => Self::MachineExternal,
+            c => Self::Unknown(c),
+        }
+    }
+
+    /// The bit for this interrupt in `xie`/`xip`
+    pub fn mask(&self) -> usize {
+        let code = match *self {
+            Self::SupervisorSoftware => 1,
+            Self::MachineSoftware => 3,
+            Self::SupervisorTimer => 5,
+            Self::MachineTimer => 7,
+            Self::SupervisorExternal => 9,
+            Self::MachineExternal => 11,
+            Self::Unknown(c) => c,
+        };
+        1usize.checked_shl(code as u32).unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadMisaligned,
+    LoadAccessFault,
+    StoreMisaligned,
+    StoreAccessFault,
+    UserEcall,
+    SupervisorEcall,
+    MachineEcall,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    Unknown(usize),
+}
+
+impl Exception {
+    pub fn from_code(code: usize) -> Self {
+        match code {
+            0 => Self::InstructionMisaligned,
+            1 => Self::InstructionAccessFault,
+            2 => Self::IllegalInstruction,
+            3 => Self::Breakpoint,
+            4 => Self::LoadMisaligned,
+            5 => Self::LoadAccessFault,
+            6 => Self::StoreMisaligned,
+            7 => Self::StoreAccessFault,
+            8 => Self::UserEcall,
+            9 => Self::SupervisorEcall,
+            11 => Self::MachineEcall,
+            12 => Self::InstructionPageFault,
+            13 => Self::LoadPageFault,
+            15 => Self::StorePageFault,
+            c => Self::Unknown(c),
+        }
+    }
+}
+
+/// A decoded `xcause` value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    Interrupt(Interrupt),
+    Exception(Exception),
+}
+
+impl Trap {
+    pub fn from_cause(cause: usize) -> Self {
+        let code = cause & !CAUSE_INTERRUPT_BIT;
+        if cause & CAUSE_INTERRUPT_BIT != 0 {
+            Self::Interrupt(Interrupt::from_code(code))
+        } else {
+            Self::Exception(Exception::from_code(code))
+        }
+    }
+}
+
+/// Length in bytes of the instruction at `addr`.
+///
+/// Anything whose lowest two bits aren't `0b11` is a compressed,
+/// 16-bit instruction.
+///
+/// # Safety
+///
+/// `addr` must point at a readable instruction.
+pub unsafe fn instruction_len(addr: usize) -> usize {
+    let half = (addr as *const u16).read_volatile();
+    if half & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Called by `s_trap_vector` with the frame it just filled in.
+#[no_mangle]
+extern "C" fn s_trap_handler(frame: &mut TrapFrame) {
+    let counters = percpu!(counters);
+    match Trap::from_cause(frame.cause) {
+        Trap::Interrupt(irq) => {
+            counters.interrupts.fetch_add(1, Ordering::Relaxed);
+            let depth = percpu!(irq_depth);
+            depth.fetch_add(1, Ordering::Relaxed);
+            handle_interrupt(frame, irq);
+            depth.fetch_sub(1, Ordering::Relaxed);
+        }
+        Trap::Exception(exc) => {
+            counters.exceptions.fetch_add(1, Ordering::Relaxed);
+            handle_exception(frame, exc);
+        }
+    }
+}
+
+fn handle_interrupt(frame: &mut TrapFrame, irq: Interrupt) {
+    match irq {
+        Interrupt::SupervisorSoftware => ipi::handle_interrupt(frame.hartid),
+        Interrupt::SupervisorTimer => timer::handle_interrupt(frame.hartid),
+        Interrupt::SupervisorExternal => plic::handle_interrupt(frame.hartid),
+        _ => {
+            // Nothing is wired up to service this source yet. Interrupts
+            // stay pending until their source is serviced, so if we
+            // returned with it still enabled we'd trap straight back here.
+            println!(
+                "hart {}: unhandled interrupt {:?}, masking it",
+                frame.hartid, irq
+            );
+            unsafe { csr::sie::clear(irq.mask()) };
+        }
+    }
+}
+
+fn handle_exception(frame: &mut TrapFrame, exc: Exception) {
+    match exc {
+        Exception::Breakpoint => {
+            println!("hart {}: breakpoint at {:#x}", frame.hartid, frame.epc);
+            frame.epc += unsafe { instruction_len(frame.epc) };
+        }
+        Exception::InstructionPageFault | Exception::LoadPageFault | Exception::StorePageFault => {
+            fault::report(frame, exc);
+            println!("{}", frame);
+            backtrace::print_from(frame.epc, frame.regs[8]);
+            panic!("page fault at {:#x}, epc {:#x}", frame.tval, frame.epc);
+        }
+        _ => {
+            println!("Unhandled exception {:?}", exc);
+            println!("{}", frame);
+            backtrace::print_from(frame.epc, frame.regs[8]);
+            panic!("fatal exception {:?} at {:#x}", exc, frame.epc);
+        }
+    }
+}
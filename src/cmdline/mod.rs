@@ -0,0 +1,224 @@
+//! The kernel command line
+//!
+//! QEMU's `-append` (or whatever loaded us) puts the command line in the
+//! `bootargs` property of the device tree's `/chosen` node. It is a space
+//! separated list of `name=value` options, or just `name` for flags.
+//! Values with spaces in them can be quoted: `init="/bin/sh -x"`.
+//!
+//! Subsystems declare the options they understand with [`kernel_param!`],
+//! which puts a reference to them in the `.kparams` section. [`init`]
+//! goes through the command line once, early in `kinit`, sets every
+//! parameter it names and complains about the ones nobody declared.
+
+use core::{cell::UnsafeCell, fmt, mem::size_of};
+
+use crate::{fdt, println};
+
+extern "C" {
+    static KPARAMS_START: usize;
+    static KPARAMS_END: usize;
+}
+
+/// A type a kernel parameter can have
+pub trait ParamValue: Copy + fmt::Debug + Sync + 'static {
+    /// `value` is `None` for a bare `name` without an `=`
+    fn parse(value: Option<&'static str>) -> Option<Self>;
+}
+
+/// Flags: `name`, `name=1`, `name=on`, `name=off`, ...
+impl ParamValue for bool {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        match value.unwrap_or("1") {
+            "1" | "y" | "yes" | "on" | "true" => Some(true),
+            "0" | "n" | "no" | "off" | "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Decimal, or hex with a `0x` prefix
+impl ParamValue for usize {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        let value = value?;
+        match value.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        }
+    }
+}
+
+impl ParamValue for &'static str {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        value
+    }
+}
+
+/// For parameters that default to "not given"
+impl<T: ParamValue> ParamValue for Option<T> {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        T::parse(value).map(Some)
+    }
+}
+
+/// A number of bytes, with an optional `K`, `M` or `G` suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size(pub usize);
+
+impl ParamValue for Size {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        let value = value?;
+        let (digits, shift) = match value.as_bytes().last()? {
+            b'k' | b'K' => (&value[..value.len() - 1], 10),
+            b'm' | b'M' => (&value[..value.len() - 1], 20),
+            b'g' | b'G' => (&value[..value.len() - 1], 30),
+            _ => (value, 0),
+        };
+        let n = usize::parse(Some(digits))?;
+        n.checked_mul(1 << shift).map(Size)
+    }
+}
+
+/// A kernel parameter of type `T`. Declare these with [`kernel_param!`].
+pub struct Param<T> {
+    name: &'static str,
+    value: UnsafeCell<T>,
+}
+
+// Values only change in `init`, while the boot hart is the only hart
+// running and before anyone has read them.
+unsafe impl<T: Sync> Sync for Param<T> {}
+
+impl<T: ParamValue> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Self {
+            name,
+            value: UnsafeCell::new(default),
+        }
+    }
+
+    /// The value from the command line, or the default
+    pub fn get(&self) -> T {
+        unsafe { *self.value.get() }
+    }
+}
+
+/// What `.kparams` points at, so parameters of any type can sit in it
+pub trait KernelParam: Sync {
+    fn name(&self) -> &'static str;
+
+    fn value(&self) -> &dyn fmt::Debug;
+
+    /// Parse `value` and store it. False if it doesn't parse.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may be reading the parameter at the same time.
+    unsafe fn set(&self, value: Option<&'static str>) -> bool;
+}
+
+impl<T: ParamValue> KernelParam for Param<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn value(&self) -> &dyn fmt::Debug {
+        unsafe { &*self.value.get() }
+    }
+
+    unsafe fn set(&self, value: Option<&'static str>) -> bool {
+        match T::parse(value) {
+            Some(v) => {
+                *self.value.get() = v;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Declare a kernel parameter and register it for [`init`]:
+///
+/// ```ignore
+/// kernel_param! {
+///     /// Most harts to bring up
+///     static SMP: usize = "smp", MAX_HARTS;
+/// }
+/// ```
+#[macro_export]
+macro_rules! kernel_param {
+    ($(#[$attr:meta])* $vis:vis static $ident:ident: $ty:ty = $name:literal, $default:expr;) => {
+        $(#[$attr])*
+        $vis static $ident: $crate::cmdline::Param<$ty> =
+            $crate::cmdline::Param::new($name, $default);
+
+        const _: () = {
+            #[used]
+            #[link_section = ".kparams"]
+            static PARAM: &dyn $crate::cmdline::KernelParam = &$ident;
+        };
+    };
+}
+
+/// Every parameter declared anywhere in the kernel
+pub fn params() -> &'static [&'static dyn KernelParam] {
+    unsafe {
+        let len = (KPARAMS_END - KPARAMS_START) / size_of::<&dyn KernelParam>();
+        core::slice::from_raw_parts(KPARAMS_START as *const &dyn KernelParam, len)
+    }
+}
+
+/// The options on a command line, as `(name, value)`
+struct Options<'a>(&'a str);
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.0.trim_start();
+        if s.is_empty() {
+            return None;
+        }
+        let mut quoted = false;
+        let end = s
+            .char_indices()
+            .find(|&(_, c)| {
+                quoted ^= c == '"';
+                c.is_whitespace() && !quoted
+            })
+            .map_or(s.len(), |(i, _)| i);
+        let (option, rest) = s.split_at(end);
+        self.0 = rest;
+        Some(match option.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim_matches('"'))),
+            None => (option, None),
+        })
+    }
+}
+
+static mut BOOTARGS: &str = "";
+
+/// The command line we booted with, empty if there wasn't one
+pub fn bootargs() -> &'static str {
+    unsafe { BOOTARGS }
+}
+
+/// Set the parameters from `/chosen/bootargs`. Runs once on the boot hart,
+/// before the other harts are started and before anything that reads a
+/// parameter.
+pub fn init() {
+    let Some(args) = fdt::get().and_then(|fdt| fdt.chosen()?.property("bootargs")?.as_str()) else {
+        return;
+    };
+    unsafe { BOOTARGS = args };
+    println!("Command line: {}", args);
+
+    for (name, value) in Options(args) {
+        let Some(param) = params().iter().find(|p| p.name() == name) else {
+            println!("Unknown command line option `{}`", name);
+            continue;
+        };
+        if !unsafe { param.set(value) } {
+            println!("Bad value for `{}`, keeping {:?}", name, param.value());
+        }
+    }
+}
@@ -1,10 +1,10 @@
 #[repr(transparent)]
 pub struct Port {
-    num: u32,
+    num: usize,
 }
 
 impl Port {
-    pub fn new(num: u32) -> Self {
+    pub fn new(num: usize) -> Self {
         Self { num }
     }
     /// # Safety
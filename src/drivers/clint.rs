@@ -0,0 +1,44 @@
+//! Driver for the Core Local Interruptor (CLINT)
+//!
+//! The CLINT holds the machine timer (`mtime`), one timer compare
+//! register (`mtimecmp`) per hart and one software interrupt pending
+//! bit (`msip`) per hart. All of these are machine level, so outside
+//! of machine mode they are reached through the SBI calls in
+//! `arch::sbi` instead of being touched directly.
+
+/// Where QEMU's `virt` machine puts the CLINT
+pub const CLINT_BASE: usize = 0x0200_0000;
+
+const MSIP_OFFSET: usize = 0x0000;
+const MTIMECMP_OFFSET: usize = 0x4000;
+const MTIME_OFFSET: usize = 0xbff8;
+
+pub struct Clint {
+    base: usize,
+}
+
+impl Clint {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// The free running machine timer
+    #[inline]
+    pub fn mtime(&self) -> u64 {
+        unsafe { ((self.base + MTIME_OFFSET) as *const u64).read_volatile() }
+    }
+
+    /// Raise the machine timer interrupt on `hart` once `mtime >= val`.
+    #[inline]
+    pub fn set_mtimecmp(&self, hart: usize, val: u64) {
+        unsafe { ((self.base + MTIMECMP_OFFSET + hart * 8) as *mut u64).write_volatile(val) }
+    }
+
+    /// Raise or clear the machine software interrupt on `hart`.
+    #[inline]
+    pub fn set_msip(&self, hart: usize, pending: bool) {
+        unsafe { ((self.base + MSIP_OFFSET + hart * 4) as *mut u32).write_volatile(pending as u32) }
+    }
+}
+
+pub static CLINT: Clint = Clint::new(CLINT_BASE);
@@ -1 +1,4 @@
+pub mod clint;
+pub mod plic;
 pub mod serial;
+pub mod sifive_test;
@@ -0,0 +1,214 @@
+//! Driver for the Platform-Level Interrupt Controller (PLIC)
+//!
+//! Every external interrupt source (UART, virtio, ...) is wired into the
+//! PLIC. Each source has a priority, and each hart context has an enable
+//! bit per source and a priority threshold. When a source above the
+//! threshold fires, the PLIC raises the external interrupt of every
+//! context it is enabled on. The context then *claims* the interrupt,
+//! services it and *completes* it.
+//!
+//! On QEMU's `virt` machine, every hart has two contexts: machine mode
+//! (`2 * hart`) and supervisor mode (`2 * hart + 1`). We only use the
+//! supervisor ones.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    arch::{irq, mm::addr::phys_to_virt, smp, MAX_HARTS},
+    fdt, println,
+    sync::spinlock::SpinLock,
+};
+
+/// Where QEMU's `virt` machine puts the PLIC, if the device tree doesn't
+/// say otherwise
+pub const PLIC_BASE: usize = 0x0c00_0000;
+
+/// What the device tree calls the PLIC
+const COMPATIBLE: &[&str] = &["riscv,plic0", "sifive,plic-1.0.0"];
+
+/// Number of interrupt sources we keep handlers for. Source 0 is
+/// reserved to mean "no interrupt".
+pub const MAX_IRQS: usize = 128;
+
+const PRIORITY_OFFSET: usize = 0x0000;
+const PENDING_OFFSET: usize = 0x1000;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM_COMPLETE: usize = 0x4;
+
+/// Priority given to sources by [`register_irq`]
+pub const DEFAULT_PRIORITY: u32 = 1;
+
+pub struct Plic {
+    base: AtomicUsize,
+    /// Held across the read-modify-write of an enable word, which any
+    /// hart may do for any context
+    enable_lock: SpinLock<()>,
+}
+
+impl Plic {
+    pub const fn new(base: usize) -> Self {
+        Self {
+            base: AtomicUsize::new(base),
+            enable_lock: SpinLock::new(()),
+        }
+    }
+
+    /// Where the registers are mapped, from the base up to the last
+    /// supervisor context we might use, as `(start, end)`
+    pub fn mmio(&self) -> (usize, usize) {
+        let base = self.base.load(Ordering::Relaxed);
+        let contexts = supervisor_context(MAX_HARTS - 1) + 1;
+        (base, base + CONTEXT_OFFSET + contexts * CONTEXT_STRIDE)
+    }
+
+    /// Move the registers, before anything has used them
+    pub fn set_base(&self, base: usize) {
+        self.base.store(base, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base.load(Ordering::Relaxed) + offset) as *mut u32
+    }
+
+    /// Set the priority of `irq`. Priority 0 means "never interrupt".
+    pub fn set_priority(&self, irq: u32, priority: u32) {
+        unsafe {
+            self.reg(PRIORITY_OFFSET + irq as usize * 4)
+                .write_volatile(priority)
+        }
+    }
+
+    pub fn is_pending(&self, irq: u32) -> bool {
+        let word = unsafe {
+            self.reg(PENDING_OFFSET + (irq as usize / 32) * 4)
+                .read_volatile()
+        };
+        word & (1 << (irq % 32)) != 0
+    }
+
+    fn enable_reg(&self, context: usize, irq: u32) -> *mut u32 {
+        self.reg(ENABLE_OFFSET + context * ENABLE_STRIDE + (irq as usize / 32) * 4)
+    }
+
+    pub fn enable(&self, context: usize, irq: u32) {
+        let reg = self.enable_reg(context, irq);
+        let _guard = self.enable_lock.lock_irqsave();
+        unsafe { reg.write_volatile(reg.read_volatile() | (1 << (irq % 32))) }
+    }
+
+    pub fn disable(&self, context: usize, irq: u32) {
+        let reg = self.enable_reg(context, irq);
+        let _guard = self.enable_lock.lock_irqsave();
+        unsafe { reg.write_volatile(reg.read_volatile() & !(1 << (irq % 32))) }
+    }
+
+    /// Only sources with a priority above `threshold` interrupt `context`.
+    pub fn set_threshold(&self, context: usize, threshold: u32) {
+        unsafe {
+            self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + THRESHOLD)
+                .write_volatile(threshold)
+        }
+    }
+
+    /// Claim the highest priority pending interrupt for `context`.
+    pub fn claim(&self, context: usize) -> Option<u32> {
+        let irq = unsafe {
+            self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + CLAIM_COMPLETE)
+                .read_volatile()
+        };
+        (irq != 0).then_some(irq)
+    }
+
+    /// Tell the PLIC we're done with `irq`, so it may fire again.
+    pub fn complete(&self, context: usize, irq: u32) {
+        unsafe {
+            self.reg(CONTEXT_OFFSET + context * CONTEXT_STRIDE + CLAIM_COMPLETE)
+                .write_volatile(irq)
+        }
+    }
+}
+
+pub static PLIC: Plic = Plic::new(phys_to_virt(PLIC_BASE));
+
+/// The supervisor mode context of `hart`
+pub const fn supervisor_context(hart: usize) -> usize {
+    hart * 2 + 1
+}
+
+/// `fn(irq)` for each source, or 0 if nothing is registered.
+static HANDLERS: [AtomicUsize; MAX_IRQS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; MAX_IRQS]
+};
+
+/// Find the PLIC in the device tree. Runs once on the boot hart, before
+/// any [`init_hart`].
+pub fn probe() {
+    let base = fdt::get()
+        .and_then(|fdt| fdt.find_compatible(COMPATIBLE))
+        .and_then(|node| node.reg().next());
+    if let Some(reg) = base {
+        PLIC.set_base(phys_to_virt(reg.address as usize));
+    }
+}
+
+/// Let every source through to `hart`'s supervisor context. Sources are
+/// still individually disabled until they are registered.
+pub fn init_hart(hart: usize) {
+    PLIC.set_threshold(supervisor_context(hart), 0);
+}
+
+/// Call `handler` whenever `irq` fires. The interrupt is routed to the
+/// boot hart; use [`enable_on`] to take it on other harts as well.
+pub fn register_irq(irq: u32, handler: fn(u32)) {
+    assert!(
+        irq != 0 && (irq as usize) < MAX_IRQS,
+        "IRQ {} is out of range",
+        irq
+    );
+    HANDLERS[irq as usize].store(handler as usize, Ordering::Release);
+    PLIC.set_priority(irq, DEFAULT_PRIORITY);
+    enable_on(smp::boot_hartid(), irq);
+}
+
+/// Stop calling the handler for `irq` and mask it everywhere.
+pub fn unregister_irq(irq: u32) {
+    assert!(
+        irq != 0 && (irq as usize) < MAX_IRQS,
+        "IRQ {} is out of range",
+        irq
+    );
+    PLIC.set_priority(irq, 0);
+    HANDLERS[irq as usize].store(0, Ordering::Release);
+}
+
+/// Route `irq` to `hart` in addition to wherever it already goes.
+pub fn enable_on(hart: usize, irq: u32) {
+    PLIC.enable(supervisor_context(hart), irq);
+}
+
+/// Called from the trap handler on a supervisor external interrupt.
+/// Services every interrupt that is pending for `hart`.
+pub fn handle_interrupt(hart: usize) {
+    irq::debug_assert_disabled();
+    let context = supervisor_context(hart);
+    while let Some(irq) = PLIC.claim(context) {
+        let handler = HANDLERS
+            .get(irq as usize)
+            .map_or(0, |h| h.load(Ordering::Acquire));
+        if handler != 0 {
+            let f: fn(u32) = unsafe { core::mem::transmute(handler) };
+            f(irq);
+        } else {
+            println!("hart {}: no handler for IRQ {}, masking it", hart, irq);
+            PLIC.disable(context, irq);
+        }
+        PLIC.complete(context, irq);
+    }
+}
@@ -1,20 +1,125 @@
 pub mod uart_16550;
 
+use core::fmt;
+
 use uart_16550::SerialPort;
 
-use crate::sync::spinlock::OnceCell;
+use crate::{
+    arch::mm::addr::phys_to_virt, cmdline::ParamValue, drivers::plic, fdt, kernel_param,
+    sync::spinlock::OnceCell,
+};
+
+/// Where QEMU's `virt` machine puts its 16550, if the device tree
+/// doesn't say otherwise
+pub const UART_BASE: usize = 0x1000_0000;
+
+/// The PLIC source the UART is wired to on QEMU's `virt` machine
+pub const UART_IRQ: u32 = 10;
+
+/// What the device tree calls the UARTs we can drive
+const COMPATIBLE: &[&str] = &["ns16550a", "ns16550"];
 
 pub static mut SERIAL: OnceCell<SerialPort> = OnceCell::new();
 
+/// Where `print!` goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /// The 16550, `console=ttyS0`
+    Uart,
+    /// The SBI debug console, `console=sbi`
+    Sbi,
+}
+
+impl ParamValue for Console {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        match value? {
+            "sbi" | "hvc0" => Some(Console::Sbi),
+            v if v.starts_with("ttyS") => Some(Console::Uart),
+            _ => None,
+        }
+    }
+}
+
+kernel_param! {
+    pub static CONSOLE: Console = "console", Console::Uart;
+}
+
+/// `loglevel=` at or above which debugging output is printed
+pub const LOGLEVEL_DEBUG: usize = 8;
+
+kernel_param! {
+    /// How much to say while booting (`loglevel=`)
+    pub static LOGLEVEL: usize = "loglevel", 7;
+}
+
+/// Should we print debugging output? See [`LOGLEVEL`].
+pub fn debug() -> bool {
+    LOGLEVEL.get() >= LOGLEVEL_DEBUG
+}
+
+/// Physical base address and PLIC source of the first UART in the device
+/// tree, falling back to [`UART_BASE`] and [`UART_IRQ`].
+fn locate() -> (usize, u32) {
+    let Some(node) = fdt::get().and_then(|fdt| fdt.find_compatible(COMPATIBLE)) else {
+        return (UART_BASE, UART_IRQ);
+    };
+    let base = node.reg().next().map_or(UART_BASE, |r| r.address as usize);
+    let irq = node.interrupts().next().unwrap_or(UART_IRQ);
+    (base, irq)
+}
+
+/// The console UART, set up on first use
+pub fn port() -> &'static SerialPort {
+    unsafe { SERIAL.get_or_init(|| SerialPort::new(phys_to_virt(locate().0))) }
+}
+
+/// Hook the UART up to the PLIC and stop polling it. Needs the PLIC
+/// context of the boot hart to be set up first.
+pub fn init_interrupts() {
+    let port = port();
+    plic::register_irq(locate().1, handle_irq);
+    port.enable_interrupts();
+}
+
+fn handle_irq(_irq: u32) {
+    if let Some(port) = unsafe { SERIAL.get() } {
+        port.handle_irq();
+    }
+}
+
+/// Push out everything still queued for the UART, see [`SerialPort::flush`].
+pub fn flush() {
+    if let Some(port) = unsafe { SERIAL.get() } {
+        port.flush();
+    }
+}
+
+/// Write raw bytes to the UART, e.g. for the SBI console putchar.
+pub fn write_bytes(bytes: &[u8]) {
+    port().writer().write_bytes(bytes);
+}
+
+/// Backs `print!`. Output goes to the [`CONSOLE`], except that until the
+/// UART is set up it goes to the firmware's console when there is
+/// firmware to ask.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let early = cfg!(feature = "riscv-sbi") && unsafe { SERIAL.get() }.is_none();
+    if early || CONSOLE.get() == Console::Sbi {
+        let _ = crate::arch::sbi::DebugConsole.write_fmt(args);
+        return;
+    }
+    let _ = port().writer().write_fmt(args);
+}
+
 #[macro_export]
 macro_rules! print {
-     	($($args:tt)+) => ({
- 			use core::fmt::Write;
-            unsafe {
- 			let _ = write!(crate::drivers::serial::SERIAL.get_or_init(|| crate::drivers::serial::uart_16550::SerialPort::new(0x1000_0000)).lock(), $($args)+);
-            }
- 	});
- }
+    ($($args:tt)+) => ({
+        $crate::drivers::serial::_print(format_args!($($args)+))
+    });
+}
 
 #[macro_export]
 macro_rules! println
@@ -1,5 +1,10 @@
 //! UART driver for the 16550 chip.
-//! Note this currently does not support reading into a buffer.
+//!
+//! The port starts out polled. Once [`SerialPort::enable_interrupts`] has
+//! been called, received bytes are moved into a ring buffer by the
+//! interrupt handler, and output is queued in a second ring buffer that
+//! the transmit-holding-register-empty interrupt drains, so writers only
+//! wait on the UART when the queue is full.
 //!
 //! # Usage Example:
 //! ```
@@ -7,10 +12,47 @@
 
 #![allow(dead_code)]
 
-use crate::{cpu::port::Port, sync::spinlock::SpinLock};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{
+    arch::irq,
+    cpu::port::Port,
+    sync::{
+        ring::RingBuffer,
+        spinlock::{Guard, SpinLock},
+        waker::AtomicWaker,
+    },
+};
+
+const RX_BUFFER_SIZE: usize = 256;
+const TX_BUFFER_SIZE: usize = 1024;
+
+/// Depth of the transmit FIFO, i.e. how many bytes we can hand the UART
+/// per THR empty interrupt.
+const TX_FIFO_SIZE: usize = 16;
+
+/// Interrupt enable register bits
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_THR_EMPTY: u8 = 1 << 1;
+
+/// Line status register bits
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
 
 pub struct SerialPort {
     regs: SpinLock<SerialInner>,
+    base: usize,
+    rx: RingBuffer<RX_BUFFER_SIZE>,
+    tx: RingBuffer<TX_BUFFER_SIZE>,
+    rx_waker: AtomicWaker,
+    irq_driven: AtomicBool,
+    /// Bytes received while `rx` was full
+    rx_dropped: AtomicUsize,
 }
 
 pub struct SerialInner {
@@ -27,7 +69,7 @@ pub struct SerialInner {
 }
 
 impl SerialInner {
-    pub fn new(base: u32) -> Self {
+    pub fn new(base: usize) -> Self {
         let regs = Self {
             data: Port::new(base),
             irq_enable: Port::new(base + 1),
@@ -57,17 +99,30 @@ impl SerialInner {
 
     const DLAB_BIT: u8 = 0b1000_0000;
 
+    /// Mask the UART's own interrupts while `f` runs. This only silences
+    /// the device, use `arch::irq` to keep the hart from being interrupted.
     fn without_irqs<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let prev = unsafe { self.irq_enable.readb() };
         unsafe {
             self.irq_enable.writeb(0x00);
         }
         let res = f(self);
         unsafe {
-            self.irq_enable.writeb(0x01);
+            self.irq_enable.writeb(prev);
         }
         res
     }
 
+    /// Turn on the interrupts in `mask`, leaving the others alone.
+    fn enable_irqs(&mut self, mask: u8) {
+        unsafe {
+            let ier = self.irq_enable.readb();
+            if ier & mask != mask {
+                self.irq_enable.writeb(ier | mask);
+            }
+        }
+    }
+
     fn set_baud_rate_divisor(&mut self, divisor: u16) -> u16 {
         let prev = self.baud_rate_divisor;
         if divisor == 0 {
@@ -111,12 +166,12 @@ impl SerialInner {
 
     #[inline]
     fn write_rdy(&self) -> bool {
-        unsafe { self.line_status.readb() & 0x20 != 0 }
+        unsafe { self.line_status.readb() & LSR_THR_EMPTY != 0 }
     }
 
     #[inline]
     fn read_rdy(&self) -> bool {
-        unsafe { self.line_status.readb() & 0x1 == 1 }
+        unsafe { self.line_status.readb() & LSR_DATA_READY != 0 }
     }
 
     #[inline]
@@ -133,7 +188,7 @@ impl core::fmt::Write for SerialInner {
 }
 
 impl SerialPort {
-    pub fn new(base_addr: u32) -> Self {
+    pub fn new(base_addr: usize) -> Self {
         let mut regs = SerialInner::new(base_addr);
 
         // Disable all interrupts
@@ -153,26 +208,199 @@ impl SerialPort {
 
         Self {
             regs: SpinLock::new(regs),
+            base: base_addr,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            rx_waker: AtomicWaker::new(),
+            irq_driven: AtomicBool::new(false),
+            rx_dropped: AtomicUsize::new(0),
         }
     }
 
-    pub fn lock(&self) -> crate::sync::spinlock::Guard<SerialInner> {
+    /// Where the registers are mapped
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn lock(&self) -> Guard<SerialInner> {
         self.regs.lock()
     }
 
+    /// A `core::fmt::Write` that goes through the transmit queue once
+    /// interrupts are enabled. Trap handlers print too, so this keeps
+    /// interrupts masked for as long as it holds the lock.
+    pub fn writer(&self) -> SerialWriter<'_> {
+        SerialWriter {
+            port: self,
+            regs: self.regs.lock_irqsave(),
+        }
+    }
+
+    /// Switch from polling to interrupt driven operation. `handle_irq`
+    /// must be hooked up to the UART's interrupt before this is called.
+    pub fn enable_interrupts(&self) {
+        self.irq_driven.store(true, Ordering::Release);
+        self.regs.lock_irqsave().enable_irqs(IER_RX_AVAILABLE);
+    }
+
+    /// Bytes thrown away because nobody read them fast enough
+    pub fn rx_dropped(&self) -> usize {
+        self.rx_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Registers accessed without the lock. Only the interrupt handler
+    /// and lock-free readers use these, and only for registers where a
+    /// racing access is harmless.
+    #[inline]
+    fn raw(&self, offset: usize) -> Port {
+        Port::new(self.base + offset)
+    }
+
+    #[inline]
+    fn raw_read_rdy(&self) -> bool {
+        unsafe { self.raw(5).readb() & LSR_DATA_READY != 0 }
+    }
+
+    /// Service the UART. Called from the interrupt handler, so it never
+    /// takes `regs`: the hart it interrupted might be holding it.
+    pub fn handle_irq(&self) {
+        irq::debug_assert_disabled();
+
+        let mut received = false;
+        while self.raw_read_rdy() {
+            let b = unsafe { self.raw(0).readb() };
+            if self.rx.push(b).is_err() {
+                self.rx_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            received = true;
+        }
+        if received {
+            self.rx_waker.wake();
+        }
+
+        let lsr = unsafe { self.raw(5).readb() };
+        if lsr & LSR_THR_EMPTY != 0 {
+            for _ in 0..TX_FIFO_SIZE {
+                match self.tx.pop() {
+                    Some(b) => unsafe { self.raw(0).writeb(b) },
+                    None => break,
+                }
+            }
+        }
+
+        if self.tx.is_empty() {
+            // Nothing left to send, stop asking for THR empty interrupts.
+            // A writer may have queued something after we checked, and
+            // might have seen the interrupt still enabled, so check again.
+            let ier = self.raw(1);
+            unsafe {
+                ier.writeb(ier.readb() & !IER_THR_EMPTY);
+                if !self.tx.is_empty() {
+                    ier.writeb(ier.readb() | IER_THR_EMPTY);
+                }
+            }
+        }
+    }
+
+    /// Wait for the next byte.
     pub fn read_char(&self) -> char {
-        while !self.regs.lock().read_rdy() {
-            core::hint::spin_loop()
+        loop {
+            if let Some(c) = self.read_char_non_blocking() {
+                return c;
+            }
+            if self.irq_driven.load(Ordering::Acquire) && irq::irqs_enabled() {
+                // The RX interrupt (or the next tick) will wake us
+                unsafe { core::arch::asm!("wfi") };
+            } else {
+                core::hint::spin_loop()
+            }
         }
-        unsafe { self.regs.lock().data.readb() as char }
     }
 
+    /// The next byte, if there is one.
     pub fn read_char_non_blocking(&self) -> Option<char> {
-        let r = self.regs.lock();
-        if r.read_rdy() {
-            unsafe { Some(r.data.readb() as char) }
-        } else {
-            None
+        if let Some(b) = self.rx.pop() {
+            return Some(b as char);
+        }
+        // Until interrupts are on (or while they are masked on this hart)
+        // nobody fills the buffer for us, so look at the UART directly.
+        let irqs_on = self.irq_driven.load(Ordering::Acquire) && irq::irqs_enabled();
+        if !irqs_on && self.raw_read_rdy() {
+            return Some(unsafe { self.raw(0).readb() } as char);
+        }
+        None
+    }
+
+    /// A future resolving to the next byte.
+    pub fn read_char_async(&self) -> ReadChar<'_> {
+        ReadChar { port: self }
+    }
+
+    /// Send whatever is still queued for the THR empty interrupt by
+    /// polling. For when nobody will take that interrupt again, like a
+    /// hart that is about to halt with interrupts off.
+    pub fn flush(&self) {
+        let regs = self.regs.lock_irqsave();
+        while let Some(b) = self.tx.pop() {
+            regs.write_char(b);
+        }
+    }
+
+    fn write_byte(&self, regs: &mut SerialInner, b: u8) {
+        if !self.irq_driven.load(Ordering::Acquire) {
+            return regs.write_char(b);
+        }
+
+        let mut b = b;
+        while let Err(rejected) = self.tx.push(b) {
+            // The queue is full. Feed the UART ourselves until there is
+            // room again rather than waiting on the interrupt.
+            b = rejected;
+            if let Some(queued) = self.tx.pop() {
+                regs.write_char(queued);
+            }
+        }
+        regs.enable_irqs(IER_THR_EMPTY);
+    }
+}
+
+/// Holds the port lock for the duration of a `write!`, see [`SerialPort::writer`].
+pub struct SerialWriter<'a> {
+    port: &'a SerialPort,
+    regs: Guard<'a, SerialInner>,
+}
+
+impl SerialWriter<'_> {
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.port.write_byte(&mut self.regs, b);
+        }
+    }
+}
+
+impl core::fmt::Write for SerialWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub struct ReadChar<'a> {
+    port: &'a SerialPort,
+}
+
+impl Future for ReadChar<'_> {
+    type Output = char;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<char> {
+        if let Some(c) = self.port.read_char_non_blocking() {
+            return Poll::Ready(c);
+        }
+        self.port.rx_waker.register(cx.waker());
+        // A byte may have arrived before the waker was in place
+        match self.port.read_char_non_blocking() {
+            Some(c) => Poll::Ready(c),
+            None => Poll::Pending,
         }
     }
 }
@@ -0,0 +1,44 @@
+//! Driver for the SiFive test finisher
+//!
+//! QEMU's `virt` machine has a tiny device that powers the machine off
+//! or resets it when the right magic is written to its one register.
+//! Supervisor mode asks for this with the SBI system reset call instead
+//! of writing to it directly.
+
+/// Where QEMU's `virt` machine puts the test finisher
+pub const SIFIVE_TEST_BASE: usize = 0x0010_0000;
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+pub struct SifiveTest {
+    base: usize,
+}
+
+impl SifiveTest {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn write(&self, val: u32) {
+        unsafe { (self.base as *mut u32).write_volatile(val) }
+    }
+
+    /// Power off, with QEMU exiting successfully
+    pub fn pass(&self) {
+        self.write(FINISHER_PASS);
+    }
+
+    /// Power off, with QEMU exiting with `code`
+    pub fn fail(&self, code: u16) {
+        self.write((code as u32) << 16 | FINISHER_FAIL);
+    }
+
+    /// Reset the whole machine
+    pub fn reset(&self) {
+        self.write(FINISHER_RESET);
+    }
+}
+
+pub static SIFIVE_TEST: SifiveTest = SifiveTest::new(SIFIVE_TEST_BASE);
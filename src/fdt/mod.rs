@@ -0,0 +1,384 @@
+//! Flattened device tree parsing
+//!
+//! Whoever boots us (QEMU or SBI firmware) describes the machine in a
+//! device tree blob and passes its physical address to the boot hart in
+//! `a1`. [`Fdt`] reads it in place: nothing is copied or allocated, every
+//! [`Node`] and [`Property`] borrows straight from the blob, so it can be
+//! used before there is a heap.
+//!
+//! The blob is a header, a memory reservation block, a structure block
+//! of big endian tokens describing the tree and a strings block holding
+//! the property names. See the devicetree specification, chapter 5.
+
+mod node;
+
+use core::fmt;
+
+pub use node::{Cells, Children, Node, Properties, Property, Reg, RegIter, StrList, U32Iter};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// We can read anything that is backwards compatible with version 16
+const FDT_COMPAT_VERSION: u32 = 16;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// How deep [`Fdt::nodes`] follows the tree
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    /// The header points outside the blob
+    Truncated,
+}
+
+impl fmt::Display for FdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic(magic) => write!(f, "bad magic {:#x}", magic),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            Self::Truncated => write!(f, "truncated blob"),
+        }
+    }
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from(be32(bytes, offset)?) << 32 | u64::from(be32(bytes, offset + 4)?))
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    reservations: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    pub fn from_bytes(blob: &'a [u8]) -> Result<Self, FdtError> {
+        let field = |n: usize| be32(blob, n * 4).ok_or(FdtError::Truncated);
+        let magic = field(0)?;
+        if magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic(magic));
+        }
+        if blob.len() < HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+        let (version, last_comp_version) = (field(5)?, field(6)?);
+        if version < FDT_COMPAT_VERSION || last_comp_version > FDT_COMPAT_VERSION {
+            return Err(FdtError::UnsupportedVersion(version));
+        }
+
+        let total_size = field(1)? as usize;
+        let blob = blob.get(..total_size).ok_or(FdtError::Truncated)?;
+        let block = |offset: u32, size: usize| {
+            let offset = offset as usize;
+            offset
+                .checked_add(size)
+                .and_then(|end| blob.get(offset..end))
+                .ok_or(FdtError::Truncated)
+        };
+        let reservations_offset = field(4)? as usize;
+        Ok(Self {
+            blob,
+            structs: block(field(2)?, field(9)? as usize)?,
+            strings: block(field(3)?, field(8)? as usize)?,
+            reservations: blob.get(reservations_offset..).ok_or(FdtError::Truncated)?,
+        })
+    }
+
+    /// Parse the blob at `addr`, trusting its header for the size.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must point at readable memory holding a device tree (or
+    /// at least the 8 bytes of a header) that stays put for `'a`.
+    pub unsafe fn from_ptr(addr: *const u8) -> Result<Self, FdtError> {
+        let header = core::slice::from_raw_parts(addr, 8);
+        let magic = be32(header, 0).unwrap_or(0);
+        if magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic(magic));
+        }
+        let total_size = be32(header, 4).unwrap_or(0) as usize;
+        Self::from_bytes(core::slice::from_raw_parts(addr, total_size))
+    }
+
+    /// Size of the whole blob, for keeping it out of the allocators
+    pub fn total_size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// The hart the tree says we were booted on
+    pub fn boot_cpuid(&self) -> u32 {
+        be32(self.blob, 28).unwrap_or(0)
+    }
+
+    fn string(&self, offset: u32) -> Option<&'a str> {
+        let rest = self.strings.get(offset as usize..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&rest[..end]).ok()
+    }
+
+    pub fn root(&self) -> Option<Node<'a>> {
+        let mut cursor = Cursor::new(self.structs, 0);
+        match cursor.next()? {
+            Token::BeginNode(name) => Some(Node {
+                fdt: *self,
+                name,
+                body: cursor.offset(),
+                cells: Cells::DEFAULT,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Every node in the tree, depth first, the root included
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            cursor: Cursor::new(self.structs, 0),
+            depth: 0,
+            cells: [Cells::DEFAULT; MAX_DEPTH],
+        }
+    }
+
+    /// The node at `path`, e.g. `"/cpus"` or `"/soc/serial@10000000"`.
+    /// Components without a unit address match the first node with that
+    /// base name.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(self.root()?, |node, name| node.child(name))
+    }
+
+    /// Enabled nodes compatible with any of `compatible`
+    pub fn compatible_nodes<'c>(
+        &self,
+        compatible: &'c [&'c str],
+    ) -> impl Iterator<Item = Node<'a>> + 'c
+    where
+        'a: 'c,
+    {
+        self.nodes()
+            .filter(move |n| n.is_enabled() && compatible.iter().any(|c| n.is_compatible(c)))
+    }
+
+    /// The first enabled node compatible with any of `compatible`
+    pub fn find_compatible(&self, compatible: &[&str]) -> Option<Node<'a>> {
+        self.compatible_nodes(compatible).next()
+    }
+
+    /// RAM, from the `reg` of every `device_type = "memory"` node
+    pub fn memory(&self) -> impl Iterator<Item = Reg> + 'a {
+        self.nodes()
+            .filter(|n| n.device_type() == Some("memory") && n.is_enabled())
+            .flat_map(|n| n.reg())
+    }
+
+    /// Entries of the memory reservation block. These and
+    /// [`reserved_memory`](Self::reserved_memory) must never be handed
+    /// out by an allocator.
+    pub fn memory_reservations(&self) -> impl Iterator<Item = Reg> + 'a {
+        self.reservations
+            .chunks_exact(16)
+            .map(|e| Reg {
+                address: be64(e, 0).unwrap_or(0),
+                size: be64(e, 8),
+            })
+            .take_while(|r| r.address != 0 || r.size != Some(0))
+    }
+
+    /// Regions described by the children of `/reserved-memory`, e.g. the
+    /// firmware's own code and data under SBI firmware
+    pub fn reserved_memory(&self) -> impl Iterator<Item = Reg> + 'a {
+        self.find_node("/reserved-memory")
+            .into_iter()
+            .flat_map(|n| n.children())
+            .flat_map(|n| n.reg())
+    }
+
+    /// The enabled `device_type = "cpu"` nodes under `/cpus`
+    pub fn cpus(&self) -> impl Iterator<Item = Cpu<'a>> + 'a {
+        self.find_node("/cpus")
+            .into_iter()
+            .flat_map(|n| n.children())
+            .filter(|n| n.device_type() == Some("cpu") && n.is_enabled())
+            .map(|node| Cpu { node })
+    }
+
+    /// Frequency of the `time` CSR. `/cpus` usually has it, but it may
+    /// be given per cpu instead.
+    pub fn timebase_frequency(&self) -> Option<u64> {
+        let freq = |n: Node<'a>| n.property("timebase-frequency")?.as_u64();
+        self.find_node("/cpus")
+            .and_then(freq)
+            .or_else(|| self.cpus().find_map(|c| freq(c.node)))
+    }
+
+    /// The `/chosen` node, where the bootloader puts things like the
+    /// command line
+    pub fn chosen(&self) -> Option<Node<'a>> {
+        self.find_node("/chosen")
+    }
+}
+
+impl fmt::Debug for Fdt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fdt")
+            .field("addr", &self.blob.as_ptr())
+            .field("size", &self.blob.len())
+            .finish()
+    }
+}
+
+/// A hart, as described by a node under `/cpus`
+#[derive(Debug, Clone, Copy)]
+pub struct Cpu<'a> {
+    pub node: Node<'a>,
+}
+
+impl<'a> Cpu<'a> {
+    pub fn hartid(&self) -> Option<usize> {
+        self.node.reg().next().map(|r| r.address as usize)
+    }
+
+    /// e.g. `"rv64imafdc_zicsr_zifencei"`
+    pub fn isa(&self) -> Option<&'a str> {
+        self.node.property("riscv,isa")?.as_str()
+    }
+
+    /// The best paging mode the hart supports, e.g. `"riscv,sv48"`
+    pub fn mmu_type(&self) -> Option<&'a str> {
+        self.node.property("mmu-type")?.as_str()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop { name: u32, value: &'a [u8] },
+}
+
+/// Walks the tokens of the structure block. Stops at `FDT_END` or at
+/// anything that doesn't parse, so a corrupt tree just looks short.
+#[derive(Clone, Copy)]
+struct Cursor<'a> {
+    structs: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(structs: &'a [u8], offset: usize) -> Self {
+        Self { structs, offset }
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn align(&mut self) {
+        self.offset = (self.offset + 3) & !3;
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let token = be32(self.structs, self.offset)?;
+            self.offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let rest = self.structs.get(self.offset..)?;
+                    let len = rest.iter().position(|&b| b == 0)?;
+                    let name = core::str::from_utf8(&rest[..len]).ok()?;
+                    self.offset += len + 1;
+                    self.align();
+                    return Some(Token::BeginNode(name));
+                }
+                FDT_END_NODE => return Some(Token::EndNode),
+                FDT_PROP => {
+                    let len = be32(self.structs, self.offset)? as usize;
+                    let name = be32(self.structs, self.offset + 4)?;
+                    let start = self.offset + 8;
+                    let value = self.structs.get(start..start.checked_add(len)?)?;
+                    self.offset = start + len;
+                    self.align();
+                    return Some(Token::Prop { name, value });
+                }
+                FDT_NOP => continue,
+                FDT_END => {
+                    self.offset = self.structs.len();
+                    return None;
+                }
+                // Garbage, treat it like the end of the tree
+                _ => {
+                    self.offset = self.structs.len();
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// See [`Fdt::nodes`]
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    cursor: Cursor<'a>,
+    depth: usize,
+    /// `cells[d]` is what the node at depth `d` declares for its
+    /// children. Properties come before subnodes, so by the time we meet
+    /// a child its parent's entry is complete.
+    cells: [Cells; MAX_DEPTH],
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            match self.cursor.next()? {
+                Token::BeginNode(name) => {
+                    let parent = self.depth.checked_sub(1);
+                    self.depth += 1;
+                    if self.depth > MAX_DEPTH {
+                        continue;
+                    }
+                    self.cells[self.depth - 1] = Cells::DEFAULT;
+                    return Some(Node {
+                        fdt: self.fdt,
+                        name,
+                        body: self.cursor.offset(),
+                        cells: parent.map_or(Cells::DEFAULT, |p| self.cells[p]),
+                    });
+                }
+                Token::EndNode => self.depth = self.depth.checked_sub(1)?,
+                Token::Prop { name, value } if self.depth <= MAX_DEPTH && self.depth > 0 => {
+                    let value = be32(value, 0).map(|v| v as usize);
+                    let cells = &mut self.cells[self.depth - 1];
+                    match (self.fdt.string(name), value) {
+                        (Some("#address-cells"), Some(v)) => cells.address = v,
+                        (Some("#size-cells"), Some(v)) => cells.size = v,
+                        _ => {}
+                    }
+                }
+                Token::Prop { .. } => {}
+            }
+        }
+    }
+}
+
+/// The device tree we were booted with, if there is one and it parses
+pub fn get() -> Option<Fdt<'static>> {
+    let ptr = crate::arch::boot::dtb_ptr()?;
+    unsafe { Fdt::from_ptr(ptr) }.ok()
+}
@@ -0,0 +1,298 @@
+//! Nodes and properties of a device tree
+
+use core::fmt;
+
+use super::{be32, be64, Cursor, Fdt, Token};
+
+/// `#address-cells` and `#size-cells` in effect for a node's `reg`, i.e.
+/// the values its parent declares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cells {
+    pub address: usize,
+    pub size: usize,
+}
+
+impl Cells {
+    /// What the spec says to assume when a node doesn't declare them
+    pub const DEFAULT: Self = Self {
+        address: 2,
+        size: 1,
+    };
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    pub(super) fdt: Fdt<'a>,
+    pub(super) name: &'a str,
+    /// Offset into the structure block of the first token after our name
+    pub(super) body: usize,
+    pub(super) cells: Cells,
+}
+
+impl<'a> Node<'a> {
+    /// The full name, unit address included (`"uart@10000000"`). The root
+    /// node's name is empty.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The name without the unit address (`"uart"`)
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or("")
+    }
+
+    /// The part of the name after the `@`, if any
+    pub fn unit_address(&self) -> Option<&'a str> {
+        self.name.split_once('@').map(|(_, addr)| addr)
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            cursor: Cursor::new(self.fdt.structs, self.body),
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    /// The direct children of this node
+    pub fn children(&self) -> Children<'a> {
+        Children {
+            fdt: self.fdt,
+            cursor: Cursor::new(self.fdt.structs, self.body),
+            depth: 0,
+            cells: self.child_cells(),
+        }
+    }
+
+    /// The child called `name`. Without a unit address in `name`, the
+    /// first child with that base name matches.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        self.children()
+            .find(|c| c.name == name || (!name.contains('@') && c.base_name() == name))
+    }
+
+    /// `#address-cells` and `#size-cells` our children's `reg` uses
+    pub fn child_cells(&self) -> Cells {
+        let read = |name, default| {
+            self.property(name)
+                .and_then(|p| p.as_u32())
+                .map_or(default, |v| v as usize)
+        };
+        Cells {
+            address: read("#address-cells", Cells::DEFAULT.address),
+            size: read("#size-cells", Cells::DEFAULT.size),
+        }
+    }
+
+    /// The strings in `compatible`, most specific first
+    pub fn compatible(&self) -> StrList<'a> {
+        StrList(self.property("compatible").map_or(&[], |p| p.value))
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    pub fn device_type(&self) -> Option<&'a str> {
+        self.property("device_type").and_then(|p| p.as_str())
+    }
+
+    /// Is the device usable? A missing `status` means it is.
+    pub fn is_enabled(&self) -> bool {
+        self.property("status")
+            .and_then(|p| p.as_str())
+            .map_or(true, |s| s == "okay" || s == "ok")
+    }
+
+    /// The (address, size) pairs in `reg`. These are in the parent bus'
+    /// address space, which on everything we run on is physical memory.
+    pub fn reg(&self) -> RegIter<'a> {
+        RegIter {
+            value: self.property("reg").map_or(&[], |p| p.value),
+            cells: self.cells,
+        }
+    }
+
+    /// The cells of `interrupts`. With the PLIC as the parent interrupt
+    /// controller that is one interrupt number per cell.
+    pub fn interrupts(&self) -> U32Iter<'a> {
+        U32Iter(self.property("interrupts").map_or(&[], |p| p.value))
+    }
+}
+
+impl fmt::Debug for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Node").field("name", &self.name).finish()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// A single cell
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => be32(self.value, 0),
+            _ => None,
+        }
+    }
+
+    /// One or two cells, as used for addresses, sizes and frequencies
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => be32(self.value, 0).map(u64::from),
+            8 => be64(self.value, 0),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_u64().map(|v| v as usize)
+    }
+
+    /// A NUL terminated string
+    pub fn as_str(&self) -> Option<&'a str> {
+        let (last, s) = self.value.split_last()?;
+        if *last != 0 {
+            return None;
+        }
+        core::str::from_utf8(s).ok()
+    }
+
+    /// A list of NUL terminated strings
+    pub fn as_str_list(&self) -> StrList<'a> {
+        StrList(self.value)
+    }
+}
+
+impl fmt::Debug for Property<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Property")
+            .field("name", &self.name)
+            .field("len", &self.value.len())
+            .finish()
+    }
+}
+
+/// One entry of a `reg` property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg {
+    pub address: u64,
+    /// `None` when the parent has `#size-cells = <0>`
+    pub size: Option<u64>,
+}
+
+/// Read `cells` cells starting at the front of `bytes`. Anything wider
+/// than 64 bits keeps the low bits.
+fn read_cells(bytes: &[u8], cells: usize) -> u64 {
+    (0..cells)
+        .filter_map(|i| be32(bytes, i * 4))
+        .fold(0, |acc, cell| acc << 32 | u64::from(cell))
+}
+
+pub struct RegIter<'a> {
+    value: &'a [u8],
+    cells: Cells,
+}
+
+impl Iterator for RegIter<'_> {
+    type Item = Reg;
+
+    fn next(&mut self) -> Option<Reg> {
+        let (address, size) = (self.cells.address * 4, self.cells.size * 4);
+        if address + size == 0 || self.value.len() < address + size {
+            return None;
+        }
+        let reg = Reg {
+            address: read_cells(self.value, self.cells.address),
+            size: (size != 0).then(|| read_cells(&self.value[address..], self.cells.size)),
+        };
+        self.value = &self.value[address + size..];
+        Some(reg)
+    }
+}
+
+pub struct U32Iter<'a>(&'a [u8]);
+
+impl Iterator for U32Iter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let v = be32(self.0, 0)?;
+        self.0 = &self.0[4..];
+        Some(v)
+    }
+}
+
+pub struct StrList<'a>(&'a [u8]);
+
+impl<'a> Iterator for StrList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let end = self.0.iter().position(|&b| b == 0)?;
+        let s = core::str::from_utf8(&self.0[..end]).ok();
+        self.0 = &self.0[end + 1..];
+        s
+    }
+}
+
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    cursor: Cursor<'a>,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        // Properties come before any child nodes
+        match self.cursor.next()? {
+            Token::Prop { name, value } => Some(Property {
+                name: self.fdt.string(name)?,
+                value,
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    cursor: Cursor<'a>,
+    /// How far below the parent we are, 0 when looking at its children
+    depth: usize,
+    cells: Cells,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            match self.cursor.next()? {
+                Token::BeginNode(name) => {
+                    self.depth += 1;
+                    if self.depth == 1 {
+                        return Some(Node {
+                            fdt: self.fdt,
+                            name,
+                            body: self.cursor.offset(),
+                            cells: self.cells,
+                        });
+                    }
+                }
+                Token::EndNode if self.depth == 0 => return None,
+                Token::EndNode => self.depth -= 1,
+                Token::Prop { .. } => {}
+            }
+        }
+    }
+}
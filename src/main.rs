@@ -3,12 +3,20 @@
 #![feature(alloc_error_handler)]
 
 pub mod arch;
+pub mod cmdline;
 pub mod cpu;
 pub mod drivers;
+pub mod fdt;
 pub mod sync;
 pub mod util;
 
+kernel_param! {
+    /// The first program to run, once there are programs (`init=`)
+    static INIT: &str = "init", "/sbin/init";
+}
+
 #[no_mangle]
 pub extern "C" fn kmain() {
-    println!("Intialization Complete. Kernel Main starting...")
+    println!("Intialization Complete. Kernel Main starting...");
+    println!("No userspace yet, not starting {}", INIT.get());
 }
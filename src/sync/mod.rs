@@ -1 +1,3 @@
+pub mod ring;
 pub mod spinlock;
+pub mod waker;
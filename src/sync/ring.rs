@@ -0,0 +1,92 @@
+//! A fixed size, lock-free ring buffer of bytes.
+//!
+//! There may only be one producer at a time, but any number of consumers.
+//! That matches how the drivers use it: an interrupt handler (or a writer
+//! holding the device lock) pushes, and anyone may pop.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub struct RingBuffer<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    /// Total number of bytes ever popped
+    head: AtomicUsize,
+    /// Total number of bytes ever pushed
+    tail: AtomicUsize,
+}
+
+/// Slots are only written by the single producer while they are free, and
+/// only read by consumers while they are full, so sharing is fine.
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two(), "RingBuffer size must be a power of two");
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= N
+    }
+
+    /// Append `b`, handing it back if the buffer is full.
+    ///
+    /// Only one producer may push at a time.
+    pub fn push(&self, b: u8) -> Result<(), u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= N {
+            return Err(b);
+        }
+        unsafe {
+            (*self.buf.get())[tail % N] = b;
+        }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove the oldest byte.
+    pub fn pop(&self) -> Option<u8> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                return None;
+            }
+            // The producer can't reuse this slot until we move `head`
+            // past it, so reading before the exchange is fine.
+            let b = unsafe { (*self.buf.get())[head % N] };
+            match self.head.compare_exchange_weak(
+                head,
+                head.wrapping_add(1),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(b),
+                Err(actual) => head = actual,
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,8 +3,13 @@ use core::{
     sync::atomic::{AtomicBool, Ordering},
 };
 
+use crate::arch::irq::{self, IrqGuard};
+
 pub struct Guard<'a, T> {
     lock: &'a SpinLock<T>,
+    /// Set by `lock_irqsave`. Fields drop after `Drop::drop` has released
+    /// the lock, so interrupts only come back on once it is free.
+    _irq: Option<IrqGuard>,
 }
 
 /// we dont need to require that `T` is `Sync` because our `Guard<T>`
@@ -39,7 +44,21 @@ impl<T> SpinLock<T> {
             core::hint::spin_loop();
         }
 
-        Guard { lock: self }
+        Guard {
+            lock: self,
+            _irq: None,
+        }
+    }
+
+    /// Like `lock`, but masks interrupts on this hart until the guard is
+    /// dropped. Use this for anything a trap handler also locks, otherwise
+    /// the handler can spin forever on a lock its own hart holds.
+    #[inline]
+    pub fn lock_irqsave(&self) -> Guard<T> {
+        let irq = irq::disable();
+        let mut guard = self.lock();
+        guard._irq = Some(irq);
+        guard
     }
 }
 
@@ -88,10 +107,19 @@ impl<T> OnceCell<T> {
         }
     }
 
+    /// The value, if it has been initialized.
+    pub fn get(&self) -> Option<&T> {
+        if self.initialized.load(Ordering::Acquire) {
+            self.value.as_ref()
+        } else {
+            None
+        }
+    }
+
     pub fn get_or_init(&mut self, f: impl FnOnce() -> T) -> &mut T {
         if !self.initialized.load(Ordering::Relaxed) {
             self.value = Some(f());
-            self.initialized.store(true, Ordering::Relaxed);
+            self.initialized.store(true, Ordering::Release);
         }
         self.value.as_mut().unwrap()
     }
@@ -0,0 +1,88 @@
+//! A `Waker` slot that can be filled by a task and fired from an
+//! interrupt handler without either side taking a lock.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+};
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 1 << 0;
+const WAKING: usize = 1 << 1;
+
+pub struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+/// `waker` is only touched by whoever moved `state` out of `WAITING`.
+unsafe impl Sync for AtomicWaker {}
+unsafe impl Send for AtomicWaker {}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Store `waker` to be woken by the next call to [`wake`](Self::wake).
+    pub fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => {
+                unsafe { *self.waker.get() = Some(waker.clone()) };
+                if self
+                    .state
+                    .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    // A wake came in while we were registering. It couldn't
+                    // take the waker, so it is up to us to fire it.
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            Err(WAKING) => {
+                // Someone is waking the old waker right now. Make sure
+                // the new one gets polled again as well.
+                waker.wake_by_ref();
+            }
+            Err(_) => {
+                // Another register is in progress. Only one task may wait
+                // on this slot, so that one wins.
+            }
+        }
+    }
+
+    /// Wake whoever is registered, if anyone.
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
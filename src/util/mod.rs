@@ -1,3 +1,4 @@
 mod addr;
 mod panic;
+pub mod symbols;
 pub use addr::*;
@@ -1,7 +1,16 @@
-use crate::println;
+use crate::{
+    arch::{backtrace, ipi},
+    drivers::serial,
+    println,
+};
 
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    ipi::stop_others();
     println!("PANIC: {:#?}", info);
+    backtrace::print_current();
+    // We may well have interrupts off for good, and the UART's interrupt
+    // handler would never send the rest
+    serial::flush();
     loop {}
 }
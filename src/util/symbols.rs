@@ -0,0 +1,135 @@
+//! Kernel symbol table
+//!
+//! The kernel carries a sorted table of its own function symbols in the
+//! `.ksymtab` section, so addresses can be turned into names at runtime.
+//! The section is linked in zeroed and filled in after the link by
+//! `tools/ksymtab.py`, which documents the layout. `cargo run` and
+//! `tools/build.sh` do that, a plain `cargo build` doesn't. A kernel that
+//! hasn't been through the tool can't symbolize anything, and says so
+//! the first time it tries.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Space reserved for the table. `tools/ksymtab.py` refuses to write a
+/// table that doesn't fit.
+const KSYMTAB_SIZE: usize = 512 * 1024;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+#[used]
+#[link_section = ".ksymtab"]
+static KSYMTAB: [u8; KSYMTAB_SIZE] = [0; KSYMTAB_SIZE];
+
+extern "C" {
+    static KSYMTAB_START: usize;
+    static KSYMTAB_END: usize;
+}
+
+struct Table {
+    entries: &'static [u8],
+    strings: &'static [u8],
+}
+
+fn u16_at(b: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(off..off + 2)?.try_into().ok()?))
+}
+
+fn u32_at(b: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn u64_at(b: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(b.get(off..off + 8)?.try_into().ok()?))
+}
+
+impl Table {
+    /// The table, if the tool has filled it in.
+    fn get() -> Option<Self> {
+        // Read through the linker's addresses rather than `KSYMTAB`, the
+        // compiler knows that one is all zeroes.
+        let raw = unsafe {
+            core::slice::from_raw_parts(
+                KSYMTAB_START as *const u8,
+                KSYMTAB_END - KSYMTAB_START,
+            )
+        };
+        if raw.get(..4)? != MAGIC || u32_at(raw, 4)? != VERSION {
+            return None;
+        }
+        let count = u32_at(raw, 8)? as usize;
+        let strings = u32_at(raw, 12)? as usize;
+        Some(Self {
+            entries: raw.get(HEADER_SIZE..HEADER_SIZE + count * ENTRY_SIZE)?,
+            strings: raw.get(strings..)?,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    fn addr(&self, i: usize) -> usize {
+        u64_at(self.entries, i * ENTRY_SIZE).unwrap_or(0) as usize
+    }
+
+    fn size(&self, i: usize) -> usize {
+        u32_at(self.entries, i * ENTRY_SIZE + 8).unwrap_or(0) as usize
+    }
+
+    fn name(&self, i: usize) -> Option<&'static str> {
+        let off = u32_at(self.entries, i * ENTRY_SIZE + 12)? as usize;
+        let len = u16_at(self.strings, off)? as usize;
+        core::str::from_utf8(self.strings.get(off + 2..off + 2 + len)?).ok()
+    }
+}
+
+/// The function containing `addr`, and how far into it `addr` is.
+///
+/// Symbols without a size (labels in the assembly) are taken to run up to
+/// the next symbol.
+pub fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    let table = Table::get()?;
+    // Index of the last symbol starting at or below `addr`
+    let (mut lo, mut hi) = (0, table.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if table.addr(mid) <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let i = lo.checked_sub(1)?;
+    let offset = addr - table.addr(i);
+    let size = table.size(i);
+    if size != 0 && offset >= size {
+        return None;
+    }
+    Some((table.name(i)?, offset))
+}
+
+/// Set once we've said that there is no table
+static NO_TABLE_NOTED: AtomicBool = AtomicBool::new(false);
+
+/// Displays an address along with the symbol it falls in, if any.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbolized(pub usize);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)?;
+        if let Some((name, offset)) = symbolize(self.0) {
+            write!(f, " <{}+{:#x}>", name, offset)?;
+        } else if Table::get().is_none() && !NO_TABLE_NOTED.swap(true, Ordering::Relaxed) {
+            // Not println!, we're in the middle of someone else's
+            write!(f, " (no symbol table, see tools/ksymtab.py)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+#!/bin/bash
+#
+# Build the kernel and embed its symbol table, i.e. what `cargo run` does
+# short of booting it. Use this for kernels that are booted some other
+# way, a plain `cargo build` leaves `.ksymtab` empty. Arguments go to
+# `cargo build`, e.g. `tools/build.sh --release --features riscv-sbi`.
+set -o pipefail
+DIR=$(dirname "$(readlink -f "$0")")
+
+KERNELS=$(cargo build --message-format=json-render-diagnostics "$@" | python3 -c '
+import json, sys
+for line in sys.stdin:
+    msg = json.loads(line)
+    if msg.get("reason") == "compiler-artifact" and msg.get("executable"):
+        print(msg["executable"])
+') || exit 1
+
+for KERNEL in $KERNELS; do
+    python3 "$DIR/ksymtab.py" "$KERNEL" || exit 1
+    echo "$KERNEL"
+done
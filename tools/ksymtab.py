@@ -0,0 +1,153 @@
+#!/usr/bin/env python3
+"""Embed a symbol table into a linked macaque kernel.
+
+The kernel reserves a zeroed `.ksymtab` section (see `util/symbols.rs`).
+This script collects every function symbol from the ELF's `.symtab`,
+demangles it, and writes a sorted table into that section in place, so
+the kernel can symbolize addresses at runtime without any debug info.
+
+Layout, all little endian:
+
+    header   magic "KSYM", version: u32, count: u32, strings: u32
+    entries  count x { addr: u64, size: u32, name: u32 }, sorted by addr
+    strings  for each name, len: u16 followed by len bytes of UTF-8
+
+`strings` is the offset of the string area from the start of the table
+and `name` is the offset of a name within the string area.
+
+Usage: ksymtab.py <kernel ELF>
+"""
+
+import re
+import struct
+import sys
+
+SECTION = b".ksymtab"
+MAGIC = b"KSYM"
+VERSION = 1
+
+SHT_SYMTAB = 2
+SHF_EXECINSTR = 0x4
+STT_NOTYPE = 0
+STT_FUNC = 2
+
+ESCAPES = {
+    "$SP$": "@",
+    "$BP$": "*",
+    "$RF$": "&",
+    "$LT$": "<",
+    "$GT$": ">",
+    "$LP$": "(",
+    "$RP$": ")",
+    "$C$": ",",
+}
+
+HASH = re.compile(r"^h[0-9a-f]{16}$")
+
+
+def demangle(name):
+    """Demangle a legacy (`_ZN...E`) Rust symbol, dropping the hash."""
+    if not (name.startswith("_ZN") and name.endswith("E")):
+        return name
+    rest = name[3:-1]
+    parts = []
+    while rest:
+        m = re.match(r"(\d+)", rest)
+        if not m:
+            return name
+        n = int(m.group(1))
+        start = len(m.group(1))
+        parts.append(rest[start:start + n])
+        rest = rest[start + n:]
+    if parts and HASH.match(parts[-1]):
+        parts.pop()
+
+    def unescape(part):
+        if part.startswith("_$"):
+            part = part[1:]
+        for k, v in ESCAPES.items():
+            part = part.replace(k, v)
+        part = re.sub(r"\$u([0-9a-f]+)\$", lambda m: chr(int(m.group(1), 16)), part)
+        return part.replace("..", "::")
+
+    return "::".join(unescape(p) for p in parts)
+
+
+def sections(elf):
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+    headers = []
+    for i in range(shnum):
+        name, typ, flags, addr, off, size, link, info, align, entsize = struct.unpack_from(
+            "<IIQQQQIIQQ", elf, shoff + i * shentsize
+        )
+        headers.append(dict(name=name, type=typ, flags=flags, addr=addr, offset=off,
+                            size=size, link=link, entsize=entsize))
+    strtab = headers[shstrndx]
+    for h in headers:
+        end = elf.index(b"\0", strtab["offset"] + h["name"])
+        h["name"] = bytes(elf[strtab["offset"] + h["name"]:end])
+    return headers
+
+
+def function_symbols(elf, headers):
+    symtab = next(h for h in headers if h["type"] == SHT_SYMTAB)
+    strtab = headers[symtab["link"]]
+    syms = {}
+    for off in range(symtab["offset"], symtab["offset"] + symtab["size"], symtab["entsize"]):
+        name, info, _, shndx, value, size = struct.unpack_from("<IBBHQQ", elf, off)
+        if info & 0xF not in (STT_FUNC, STT_NOTYPE):
+            continue
+        if shndx == 0 or shndx >= len(headers):
+            continue
+        if not headers[shndx]["flags"] & SHF_EXECINSTR:
+            continue
+        end = elf.index(b"\0", strtab["offset"] + name)
+        raw = elf[strtab["offset"] + name:end].decode("utf-8", "replace")
+        # Skip assembler temporaries and mapping symbols
+        if not raw or raw.startswith(".L") or raw.startswith("$"):
+            continue
+        # Keep the sized (i.e. real function) symbol if two share an address
+        if value not in syms or (syms[value][0] == 0 and size != 0):
+            syms[value] = (size, demangle(raw))
+    return sorted((addr, size, name) for addr, (size, name) in syms.items())
+
+
+def build(symbols):
+    strings = bytearray()
+    entries = bytearray()
+    for addr, size, name in symbols:
+        encoded = name.encode()[:0xFFFF]
+        entries += struct.pack("<QII", addr, min(size, 0xFFFFFFFF), len(strings))
+        strings += struct.pack("<H", len(encoded)) + encoded
+    header = struct.pack("<4sIII", MAGIC, VERSION, len(symbols), 16 + len(entries))
+    return header + entries + strings
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__.strip().splitlines()[-1])
+    path = sys.argv[1]
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+    if elf[:4] != b"\x7fELF" or elf[4] != 2:
+        sys.exit(f"{path}: not a 64-bit ELF")
+
+    headers = sections(elf)
+    section = next((h for h in headers if h["name"] == SECTION), None)
+    if section is None:
+        sys.exit(f"{path}: no {SECTION.decode()} section")
+
+    table = build(function_symbols(elf, headers))
+    if len(table) > section["size"]:
+        sys.exit(f"{path}: symbol table needs {len(table)} bytes, "
+                 f"{SECTION.decode()} only has {section['size']}")
+
+    start = section["offset"]
+    elf[start:start + section["size"]] = table.ljust(section["size"], b"\0")
+    with open(path, "wb") as f:
+        f.write(elf)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,23 @@
+#!/bin/bash
+#
+# Cargo runner: embed the symbol table into the kernel, then boot it.
+# Anything after the kernel path (e.g. `-s -S` from `cargo d`) goes to qemu.
+DIR=$(dirname "$(readlink -f "$0")")
+
+KERNEL=$1
+shift
+
+python3 "$DIR/ksymtab.py" "$KERNEL" || exit 1
+
+# Kernels built with `riscv-sbi` are linked at 0x80200000 and expect
+# firmware below them, everything else owns machine mode.
+ENTRY=$(od -An -tx8 -j24 -N8 "$KERNEL" | tr -d ' ')
+if [ "$ENTRY" = "0000000080200000" ]; then
+    BIOS=default
+else
+    BIOS=none
+fi
+
+# QEMU_CPU picks the paging modes the harts have, e.g. `rv64,sv57=off`
+# tops out at Sv48 and `rv64,sv48=off` at Sv39.
+exec qemu-system-riscv64 -machine virt -cpu "${QEMU_CPU:-rv64}" -smp 4 -m 512M -serial mon:stdio -bios $BIOS -kernel "$KERNEL" "$@"